@@ -0,0 +1,89 @@
+use std::fmt;
+
+use reqwest::{header, ClientBuilder, RequestBuilder, Version};
+use serde::Serialize;
+
+/// Whether connections outlive their request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ArgEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reuse {
+    /// a new connection per request, closed after the response like the
+    /// server does by itself
+    Fresh,
+    /// idle connections are pooled and reused
+    KeepAlive,
+}
+
+impl Reuse {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reuse::Fresh => "fresh",
+            Reuse::KeepAlive => "keep-alive",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ArgEnum)]
+pub enum HttpVersion {
+    #[clap(name = "1.0")]
+    #[serde(rename = "1.0")]
+    Http10,
+    #[clap(name = "1.1")]
+    #[serde(rename = "1.1")]
+    Http11,
+}
+
+impl HttpVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpVersion::Http10 => "1.0",
+            HttpVersion::Http11 => "1.1",
+        }
+    }
+}
+
+/// How the crawler talks to the server, noted on every record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionMode {
+    pub reuse: Reuse,
+    /// idle connections kept per host at most, unbounded by default
+    pub pool_size: Option<usize>,
+    pub version: HttpVersion,
+}
+
+impl ConnectionMode {
+    pub fn configure_client(&self, builder: ClientBuilder) -> ClientBuilder {
+        let builder = builder.http1_only();
+        match (self.reuse, self.pool_size) {
+            (Reuse::Fresh, _) => builder.pool_max_idle_per_host(0),
+            (Reuse::KeepAlive, Some(size)) => builder.pool_max_idle_per_host(size),
+            (Reuse::KeepAlive, None) => builder,
+        }
+    }
+
+    pub fn configure_request(&self, builder: RequestBuilder) -> RequestBuilder {
+        let builder = match self.version {
+            HttpVersion::Http10 => builder.version(Version::HTTP_10),
+            HttpVersion::Http11 => builder.version(Version::HTTP_11),
+        };
+        // HTTP/1.0 connections are closed unless asked otherwise, and
+        // HTTP/1.1 ones are kept unless asked otherwise
+        match (self.reuse, self.version) {
+            (Reuse::Fresh, HttpVersion::Http11) => builder.header(header::CONNECTION, "close"),
+            (Reuse::KeepAlive, HttpVersion::Http10) => {
+                builder.header(header::CONNECTION, "keep-alive")
+            }
+            _ => builder,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP/{} {}", self.version.as_str(), self.reuse.as_str())?;
+        match (self.reuse, self.pool_size) {
+            (Reuse::KeepAlive, Some(size)) => write!(f, " (pool of {})", size),
+            _ => Ok(()),
+        }
+    }
+}
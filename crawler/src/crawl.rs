@@ -0,0 +1,609 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::{
+    sync::{Notify, Semaphore},
+    time::{sleep_until, timeout_at, Instant},
+};
+
+use crate::{
+    connection::ConnectionMode,
+    extract::{extract_css_urls, extract_links, Link, LinkKind},
+    failure::{ErrorClass, Failure, RetryPolicy},
+    pace::Pacer,
+    record::{Exchange, Recorder},
+    replay::Trace,
+    verify::Corpus,
+};
+
+/// Resolves `link` found on `base` to an absolute URL without fragment.
+/// Links which cannot be fetched over HTTP (`mailto:`, `javascript:`, ...) give `None`.
+pub fn resolve_link(base: &Url, link: &str) -> Option<Url> {
+    let mut url = base.join(link.trim()).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    url.set_fragment(None);
+    Some(url)
+}
+
+/// Which kinds of links a pass follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Resources {
+    /// pages only, like a user who never loads images
+    Pages,
+    /// the target page and the assets it loads, without following links
+    Assets,
+    /// pages and their assets, like a browser
+    All,
+}
+
+impl Resources {
+    fn follows(&self, kind: LinkKind) -> bool {
+        match self {
+            Resources::Pages => kind == LinkKind::Page,
+            Resources::Assets => kind == LinkKind::Asset,
+            Resources::All => true,
+        }
+    }
+}
+
+/// Which URLs a pass may fetch.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub origin: url::Origin,
+    /// how many page links to follow from the target, assets do not count
+    pub max_depth: Option<usize>,
+    /// how many URLs to fetch per pass
+    pub max_pages: Option<usize>,
+    pub cross_origin: bool,
+    pub resources: Resources,
+}
+
+/// Outcome counts of the requests of a pass.
+#[derive(Debug, Default)]
+struct Counters {
+    fetched: AtomicUsize,
+    /// requests which failed after their last retry
+    errors: AtomicUsize,
+    retries: AtomicUsize,
+}
+
+impl Counters {
+    fn summary(&self, elapsed: Duration) -> PassSummary {
+        PassSummary {
+            fetched: self.fetched.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            elapsed,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Frontier {
+    queue: VecDeque<(Url, usize)>,
+    in_flight: usize,
+}
+
+/// One crawl over the site.
+#[derive(Debug)]
+struct Pass {
+    scope: Scope,
+    /// URLs fetched in this pass, so that each is fetched once and cycles end
+    visited: Mutex<HashSet<Url>>,
+    frontier: Mutex<Frontier>,
+    /// signalled whenever a request finishes
+    changed: Notify,
+    counters: Counters,
+}
+
+impl Pass {
+    fn new(scope: Scope) -> Self {
+        Self {
+            scope,
+            visited: Default::default(),
+            frontier: Default::default(),
+            changed: Notify::new(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Claims `url` for fetching. False if it was already fetched, is out of
+    /// scope or the page budget is spent.
+    fn claim(&self, url: &Url, depth: usize) -> bool {
+        let scope = &self.scope;
+        if scope.max_depth.is_some_and(|max| depth > max) {
+            return false;
+        }
+        if !scope.cross_origin && url.origin() != scope.origin {
+            return false;
+        }
+        let mut visited = self.visited.lock().unwrap();
+        if scope.max_pages.is_some_and(|max| visited.len() >= max) {
+            return false;
+        }
+        visited.insert(url.clone())
+    }
+
+    fn push(&self, url: Url, depth: usize) {
+        if self.claim(&url, depth) {
+            self.frontier.lock().unwrap().queue.push_back((url, depth));
+        }
+    }
+
+    /// The next URL to fetch, waiting for requests in flight to discover
+    /// more. `None` once the pass has nothing left.
+    async fn next(&self) -> Option<(Url, usize)> {
+        loop {
+            let changed = self.changed.notified();
+            {
+                let mut frontier = self.frontier.lock().unwrap();
+                if let Some(next) = frontier.queue.pop_front() {
+                    frontier.in_flight += 1;
+                    return Some(next);
+                }
+                if frontier.in_flight == 0 {
+                    return None;
+                }
+            }
+            changed.await;
+        }
+    }
+
+    fn done(&self) {
+        self.frontier.lock().unwrap().in_flight -= 1;
+        self.changed.notify_waiters();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassSummary {
+    pub fetched: usize,
+    pub errors: usize,
+    pub retries: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for PassSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} fetched, {} failed, {} retried in {:.3}s ({:.1} req/s)",
+            self.fetched,
+            self.errors,
+            self.retries,
+            self.elapsed.as_secs_f64(),
+            (self.fetched + self.errors + self.retries) as f64 / self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// A server the requests are sent to, e.g. `wasmedge=http://0.0.0.0:1234`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub label: Arc<str>,
+    pub url: Url,
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, url) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("expected <label>=<url>: {}", s))?;
+        if label.is_empty() {
+            return Err(anyhow::anyhow!("empty target label: {}", s));
+        }
+        Ok(Self {
+            label: label.into(),
+            url: url.parse()?,
+        })
+    }
+}
+
+/// How a request is sent to several targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum FanOut {
+    /// to one target after the other, so that they never compete
+    Interleaved,
+    /// to every target at once
+    Parallel,
+}
+
+/// Sends the requests of the crawler, retrying and recording them.
+#[derive(Debug, Clone)]
+pub struct Requester {
+    client: reqwest::Client,
+    mode: ConnectionMode,
+    retry: RetryPolicy,
+    recorder: Recorder,
+    /// files to check the successful responses against
+    corpus: Option<Arc<Corpus>>,
+    /// servers every request goes to, the first one's origin is the one
+    /// of the URLs to send
+    targets: Vec<Target>,
+    fan_out: FanOut,
+}
+
+impl Requester {
+    pub fn new(
+        timeout: Duration,
+        mode: ConnectionMode,
+        retry: RetryPolicy,
+        recorder: Recorder,
+    ) -> anyhow::Result<Self> {
+        let client = mode.configure_client(reqwest::Client::builder().timeout(timeout));
+        Ok(Self {
+            client: client.build()?,
+            mode,
+            retry,
+            recorder,
+            corpus: None,
+            targets: Vec::new(),
+            fan_out: FanOut::Parallel,
+        })
+    }
+
+    pub fn with_corpus(mut self, corpus: Corpus) -> Self {
+        self.corpus = Some(Arc::new(corpus));
+        self
+    }
+
+    /// Sends the requests to each of `targets` instead of their own origin,
+    /// labelled so that the results can be told apart.
+    pub fn with_targets(mut self, targets: Vec<Target>, fan_out: FanOut) -> Self {
+        self.targets = targets;
+        self.fan_out = fan_out;
+        self
+    }
+
+    /// Sends `url` to every target if it has the origin of the first one,
+    /// and as is otherwise. The links are the ones of the first successful
+    /// response.
+    async fn send(&self, counters: &Counters, url: &Url, links: bool) -> Option<Vec<Link>> {
+        let targets = match self.targets.first() {
+            Some(first) if url.origin() == first.url.origin() => &self.targets[..],
+            _ => return self.send_to(counters, url, None, links).await,
+        };
+        let requests = targets.iter().map(|target| {
+            let mut rebased = target.url.clone();
+            rebased.set_path(url.path());
+            rebased.set_query(url.query());
+            (rebased, target.label.clone())
+        });
+
+        let mut found = None;
+        match self.fan_out {
+            FanOut::Interleaved => {
+                for (url, label) in requests {
+                    let links = self.send_to(counters, &url, Some(label), links).await;
+                    found = found.or(links);
+                }
+            }
+            FanOut::Parallel => {
+                let requests = requests.collect::<Vec<_>>();
+                let sent =
+                    futures::future::join_all(requests.iter().map(|(url, label)| {
+                        self.send_to(counters, url, Some(label.clone()), links)
+                    }))
+                    .await;
+                found = sent.into_iter().flatten().next();
+            }
+        }
+        found
+    }
+
+    /// Sends a GET to `url` until it succeeds or the retry policy gives up,
+    /// recording every attempt. With `links`, gives the links of the page
+    /// if it is HTML, or the URLs of the stylesheet if it is CSS.
+    async fn send_to(
+        &self,
+        counters: &Counters,
+        url: &Url,
+        target: Option<Arc<str>>,
+        links: bool,
+    ) -> Option<Vec<Link>> {
+        let mut attempt = 1;
+        loop {
+            let mut exchange = Exchange::new(url.clone(), target.clone(), attempt, self.mode);
+            match self.attempt(&mut exchange, links).await {
+                Ok(links) => {
+                    counters.fetched.fetch_add(1, Ordering::Relaxed);
+                    tracing::info!("{}: {}", exchange.status.unwrap_or_default(), url);
+                    self.recorder.record(exchange);
+                    return Some(links);
+                }
+                Err(failure) => {
+                    tracing::warn!("{}: {}", url, failure);
+                    let retry = self.retry.retry(attempt, failure.class);
+                    exchange.failure = Some(failure);
+                    self.recorder.record(exchange);
+                    match retry {
+                        Some(backoff) => {
+                            counters.retries.fetch_add(1, Ordering::Relaxed);
+                            tokio::time::sleep(backoff).await;
+                            attempt += 1;
+                        }
+                        None => {
+                            counters.errors.fetch_add(1, Ordering::Relaxed);
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn attempt(&self, exchange: &mut Exchange, links: bool) -> Result<Vec<Link>, Failure> {
+        let result = async {
+            let request = self.client.get(exchange.url.clone());
+            let resp = self.mode.configure_request(request).send().await?;
+            exchange.status = Some(resp.status());
+            exchange.ttfb = Some(exchange.start.elapsed());
+            let content_type = resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim_start().to_ascii_lowercase());
+            let body = resp.bytes().await?;
+            exchange.bytes = body.len() as u64;
+            Ok::<_, Failure>((content_type, body))
+        }
+        .await;
+        exchange.latency = exchange.start.elapsed();
+        let (content_type, body) = result?;
+
+        match (exchange.status, &self.corpus) {
+            (Some(status), _) if status.is_server_error() => {
+                return Err(Failure::new(ErrorClass::ServerError, status));
+            }
+            (Some(status), Some(corpus)) if status.is_success() => {
+                corpus
+                    .verify(&exchange.url, content_type.as_deref(), &body)
+                    .await?;
+            }
+            _ => {}
+        }
+        if !links {
+            return Ok(Vec::new());
+        }
+        // a response which does not say what it is may be a page
+        match content_type.as_deref() {
+            None => {}
+            Some(t) if t.starts_with("text/html") => {}
+            Some(t) if t.starts_with("text/css") => {
+                let css = String::from_utf8_lossy(&body);
+                return Ok(extract_css_urls(&css).map(Link::asset).collect());
+            }
+            Some(_) => return Ok(Vec::new()),
+        }
+        extract_links(&String::from_utf8_lossy(&body))
+            .map_err(|e| Failure::new(ErrorClass::Parse, e))
+    }
+}
+
+#[derive(Debug)]
+pub struct Crawler {
+    requester: Requester,
+    target: Url,
+    scope: Scope,
+    /// requests in flight at most
+    concurrency: usize,
+}
+
+impl Crawler {
+    pub fn new(target: Url, scope: Scope, concurrency: usize, requester: Requester) -> Self {
+        Self {
+            requester,
+            target,
+            scope,
+            concurrency: concurrency.max(1),
+        }
+    }
+
+    /// Crawls the site once from the target.
+    ///
+    /// Requests start as soon as one of the `concurrency` slots is free, or
+    /// when `pacer` says so if there is one. At `deadline` no more requests
+    /// are started and the ones in flight are awaited.
+    pub async fn pass(
+        &self,
+        mut pacer: Option<&mut Pacer>,
+        deadline: Option<Instant>,
+    ) -> PassSummary {
+        let start = Instant::now();
+        let pass = Arc::new(Pass::new(self.scope.clone()));
+        pass.push(self.target.clone(), 0);
+        let slots = Arc::new(Semaphore::new(self.concurrency));
+
+        let dispatch = async {
+            loop {
+                let slot = slots.clone().acquire_owned().await.expect("never closed");
+                let (url, depth) = match pass.next().await {
+                    Some(next) => next,
+                    None => break,
+                };
+                if let Some(pacer) = pacer.as_mut() {
+                    pacer.wait().await;
+                }
+                let (requester, pass) = (self.requester.clone(), pass.clone());
+                tokio::spawn(async move {
+                    fetch(&requester, &pass, url, depth).await;
+                    pass.done();
+                    drop(slot);
+                });
+            }
+        };
+        match deadline {
+            Some(deadline) => {
+                let _ = timeout_at(deadline, dispatch).await;
+            }
+            None => dispatch.await,
+        }
+        // every slot is back once the requests in flight are done
+        let _ = slots.acquire_many(self.concurrency as u32).await;
+
+        pass.counters.summary(start.elapsed())
+    }
+
+    /// Sends the requests of `trace` in order, at their recorded times
+    /// divided by `speed`. Requests without a time follow `pacer`, or go as
+    /// fast as the concurrency allows.
+    pub async fn replay(
+        &self,
+        trace: &Trace,
+        mut pacer: Option<&mut Pacer>,
+        speed: f64,
+        deadline: Option<Instant>,
+    ) -> PassSummary {
+        let start = Instant::now();
+        let counters = Arc::new(Counters::default());
+        let slots = Arc::new(Semaphore::new(self.concurrency));
+
+        let dispatch = async {
+            for request in trace.requests() {
+                match (request.at, pacer.as_mut()) {
+                    (Some(at), _) => sleep_until(start + at.div_f64(speed)).await,
+                    (None, Some(pacer)) => pacer.wait().await,
+                    (None, None) => {}
+                }
+                let slot = slots.clone().acquire_owned().await.expect("never closed");
+                let (requester, counters, url) = (
+                    self.requester.clone(),
+                    counters.clone(),
+                    request.url.clone(),
+                );
+                tokio::spawn(async move {
+                    requester.send(&counters, &url, false).await;
+                    drop(slot);
+                });
+            }
+        };
+        match deadline {
+            Some(deadline) => {
+                let _ = timeout_at(deadline, dispatch).await;
+            }
+            None => dispatch.await,
+        }
+        let _ = slots.acquire_many(self.concurrency as u32).await;
+
+        counters.summary(start.elapsed())
+    }
+}
+
+async fn fetch(requester: &Requester, pass: &Pass, url: Url, depth: usize) {
+    let links = requester.send(&pass.counters, &url, true).await;
+    for link in links.unwrap_or_default() {
+        if !pass.scope.resources.follows(link.kind) {
+            continue;
+        }
+        // assets are part of the page which loads them
+        let depth = match link.kind {
+            LinkKind::Page => depth + 1,
+            LinkKind::Asset => depth,
+        };
+        if let Some(url) = resolve_link(&url, &link.url) {
+            pass.push(url, depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_link() {
+        let base = Url::parse("http://0.0.0.0:1234/archives/1972/").unwrap();
+        let resolve = |link| resolve_link(&base, link).map(String::from);
+
+        assert_eq!(resolve("/567").as_deref(), Some("http://0.0.0.0:1234/567"));
+        assert_eq!(
+            resolve("img/a.png").as_deref(),
+            Some("http://0.0.0.0:1234/archives/1972/img/a.png")
+        );
+        assert_eq!(
+            resolve("../1994/#comments").as_deref(),
+            Some("http://0.0.0.0:1234/archives/1994/")
+        );
+        assert_eq!(
+            resolve("https://example.com/x").as_deref(),
+            Some("https://example.com/x")
+        );
+        assert_eq!(resolve("mailto:someone@example.com"), None);
+        assert_eq!(resolve("javascript:void(0)"), None);
+    }
+
+    #[test]
+    fn test_target_from_str() {
+        let target = "wasmedge=http://0.0.0.0:1234/".parse::<Target>().unwrap();
+        assert_eq!(&*target.label, "wasmedge");
+        assert_eq!(target.url.as_str(), "http://0.0.0.0:1234/");
+
+        assert!("http://0.0.0.0:1234/".parse::<Target>().is_err());
+        assert!("=http://0.0.0.0:1234/".parse::<Target>().is_err());
+        assert!("native=0.0.0.0:1234".parse::<Target>().is_err());
+    }
+
+    fn scope(max_depth: Option<usize>, max_pages: Option<usize>, cross_origin: bool) -> Scope {
+        Scope {
+            origin: Url::parse("http://0.0.0.0:1234/").unwrap().origin(),
+            max_depth,
+            max_pages,
+            cross_origin,
+            resources: Resources::All,
+        }
+    }
+
+    #[test]
+    fn test_claim() {
+        let url = |s| Url::parse(s).unwrap();
+
+        let pass = Pass::new(scope(Some(1), None, false));
+        assert!(pass.claim(&url("http://0.0.0.0:1234/"), 0));
+        assert!(!pass.claim(&url("http://0.0.0.0:1234/"), 1));
+        assert!(pass.claim(&url("http://0.0.0.0:1234/a"), 1));
+        assert!(!pass.claim(&url("http://0.0.0.0:1234/b"), 2));
+        assert!(!pass.claim(&url("http://example.com/"), 1));
+
+        let pass = Pass::new(scope(None, Some(1), true));
+        assert!(pass.claim(&url("http://example.com/"), 0));
+        assert!(!pass.claim(&url("http://0.0.0.0:1234/a"), 1));
+    }
+
+    #[tokio::test]
+    async fn test_next_waits_for_in_flight() {
+        let pass = Arc::new(Pass::new(scope(None, None, false)));
+        pass.push(Url::parse("http://0.0.0.0:1234/").unwrap(), 0);
+
+        let (first, _) = pass.next().await.unwrap();
+        let discovering = {
+            let pass = pass.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                pass.push(first.join("/a").unwrap(), 1);
+                pass.done();
+            })
+        };
+        // the queue is empty but the first request may still find links
+        let (second, depth) = pass.next().await.unwrap();
+        assert_eq!((second.path(), depth), ("/a", 1));
+        discovering.await.unwrap();
+
+        pass.done();
+        assert_eq!(pass.next().await, None);
+    }
+}
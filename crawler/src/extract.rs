@@ -0,0 +1,205 @@
+/// What a link leads to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkKind {
+    /// another page, which a user would navigate to
+    Page,
+    /// a stylesheet, script, image or font the browser loads with the page
+    Asset,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub url: String,
+    pub kind: LinkKind,
+}
+
+impl Link {
+    pub fn page(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            kind: LinkKind::Page,
+        }
+    }
+
+    pub fn asset(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            kind: LinkKind::Asset,
+        }
+    }
+}
+
+/// `<link rel>` values whose target the browser loads with the page.
+const ASSET_RELS: [&str; 5] = ["stylesheet", "icon", "preload", "modulepreload", "manifest"];
+
+/// The links of an HTML page: `<a href>` and non-asset `<link href>` to
+/// pages, and `<link rel="stylesheet" href>` and the like, `<script src>`,
+/// `<img src|srcset>`, `<source src|srcset>` and CSS `url(...)` in `<style>`
+/// and `style` attributes to assets. Tags without the attribute are skipped.
+pub fn extract_links(body: &str) -> Result<Vec<Link>, tl::ParseError> {
+    let dom = tl::parse(body, tl::ParserOptions::default())?;
+    let parser = dom.parser();
+    let tags = |selector| {
+        dom.query_selector(selector)
+            .into_iter()
+            .flatten()
+            .filter_map(|node| node.get(parser)?.as_tag())
+    };
+    let attribute = |tag: &tl::HTMLTag, name| {
+        tag.attributes()
+            .get(name)
+            .flatten()
+            .map(|value| value.as_utf8_str().into_owned())
+    };
+
+    let mut links = Vec::new();
+    for tag in tags("a[href]") {
+        links.extend(attribute(tag, "href").map(Link::page));
+    }
+    for tag in tags("link[href]") {
+        let rel = attribute(tag, "rel")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let asset = rel.split_whitespace().any(|rel| ASSET_RELS.contains(&rel));
+        links.extend(attribute(tag, "href").map(if asset { Link::asset } else { Link::page }));
+    }
+    for (selector, name) in [
+        ("script[src]", "src"),
+        ("img[src]", "src"),
+        ("source[src]", "src"),
+    ] {
+        for tag in tags(selector) {
+            links.extend(attribute(tag, name).map(Link::asset));
+        }
+    }
+    for selector in ["img[srcset]", "source[srcset]"] {
+        for tag in tags(selector) {
+            let srcset = attribute(tag, "srcset").unwrap_or_default();
+            links.extend(parse_srcset(&srcset).map(Link::asset));
+        }
+    }
+    for tag in tags("style") {
+        links.extend(extract_css_urls(&tag.inner_text(parser)).map(Link::asset));
+    }
+    for tag in tags("[style]") {
+        let style = attribute(tag, "style").unwrap_or_default();
+        links.extend(extract_css_urls(&style).map(Link::asset));
+    }
+    Ok(links)
+}
+
+/// The URLs of a `srcset`, e.g. `a.png` and `b.png` of `a.png 1x, b.png 2x`.
+fn parse_srcset(srcset: &str) -> impl Iterator<Item = &str> {
+    srcset
+        .split(',')
+        .filter_map(|candidate| candidate.split_whitespace().next())
+}
+
+/// The `url(...)` and `@import "..."` references of a stylesheet.
+pub fn extract_css_urls(css: &str) -> impl Iterator<Item = String> + '_ {
+    let urls = css.match_indices("url(").filter_map(|(i, _)| {
+        let rest = &css[i + "url(".len()..];
+        let url = rest[..rest.find(')')?].trim();
+        Some(unquote(url))
+    });
+    let imports = css.match_indices("@import").filter_map(|(i, _)| {
+        let rest = css[i + "@import".len()..].trim_start();
+        // `@import url(...)` is found above
+        let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let end = rest[1..].find(quote)?;
+        Some(&rest[1..end + 1])
+    });
+    urls.chain(imports)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+}
+
+fn unquote(s: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(s) = s.strip_prefix(quote).and_then(|s| s.strip_suffix(quote)) {
+            return s;
+        }
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(links: &[Link], kind: LinkKind) -> Vec<&str> {
+        links
+            .iter()
+            .filter(|link| link.kind == kind)
+            .map(|link| link.url.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let links =
+            extract_links(r#"hello <img src="/foo-bar.png"> <a href="/567">good</a>"#).unwrap();
+        assert_eq!(links, [Link::page("/567"), Link::asset("/foo-bar.png")]);
+
+        let links = extract_links(r#"<img><a>not attrs!</a>"#).unwrap();
+        assert!(links.is_empty());
+
+        let links = extract_links(r#"no tags!"#).unwrap();
+        assert!(links.is_empty());
+
+        // unclosed and malformed tags do not stop the rest
+        let links = extract_links(r#"<a href="/1"><img src=><a href='/2' <div></a"#).unwrap();
+        assert!(links.contains(&Link::page("/1")));
+    }
+
+    #[test]
+    fn test_extract_assets() {
+        let links = extract_links(
+            r#"<head>
+<link rel="stylesheet" href="/style.css">
+<link rel="icon" href="/favicon.ico">
+<link rel="canonical" href="/archives/1972/">
+<script src="/app.js"></script>
+<style>body { background: url("/bg.png") }</style>
+</head>
+<body>
+<img src="/a.png" srcset="/a-480.png 480w, /a-800.png 800w">
+<picture><source srcset="/b.webp 1x, /b@2x.webp 2x"><source src="/c.mp4"></picture>
+<div style="background-image: url('/d.png')"></div>
+</body>"#,
+        )
+        .unwrap();
+
+        assert_eq!(urls(&links, LinkKind::Page), ["/archives/1972/"]);
+        let mut assets = urls(&links, LinkKind::Asset);
+        assets.sort_unstable();
+        assert_eq!(
+            assets,
+            [
+                "/a-480.png",
+                "/a-800.png",
+                "/a.png",
+                "/app.js",
+                "/b.webp",
+                "/b@2x.webp",
+                "/bg.png",
+                "/c.mp4",
+                "/d.png",
+                "/favicon.ico",
+                "/style.css",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_css_urls() {
+        let css = r#"@import "base.css";
+@import url(print.css) print;
+body { background: url( 'img/bg.png' ) }
+@font-face { src: url(/fonts/a.woff2) format("woff2"), url("") }"#;
+        assert_eq!(
+            extract_css_urls(css).collect::<Vec<_>>(),
+            ["print.css", "img/bg.png", "/fonts/a.woff2", "base.css"]
+        );
+    }
+}
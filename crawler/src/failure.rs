@@ -0,0 +1,196 @@
+use std::{error::Error as _, fmt, io, time::Duration};
+
+use serde::Serialize;
+
+/// Why a request failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, clap::ArgEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    /// nothing listens on the port, e.g. while the instance restarts
+    Refused,
+    /// the connection was closed before the response was complete
+    Reset,
+    /// no complete response within the client timeout
+    Timeout,
+    /// a 5xx response
+    ServerError,
+    /// a page whose links could not be read
+    Parse,
+    /// a body shorter than its file in the corpus
+    Truncated,
+    /// a body different from its file in the corpus
+    Mismatch,
+    /// a content type which does not match the extension of the file
+    ContentType,
+    Other,
+}
+
+impl ErrorClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Refused => "refused",
+            ErrorClass::Reset => "reset",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::ServerError => "server-error",
+            ErrorClass::Parse => "parse",
+            ErrorClass::Truncated => "truncated",
+            ErrorClass::Mismatch => "mismatch",
+            ErrorClass::ContentType => "content-type",
+            ErrorClass::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(class: ErrorClass, message: impl fmt::Display) -> Self {
+        Self {
+            class,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.class, self.message)
+    }
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        let class = match io_error_kind(&e) {
+            _ if e.is_timeout() => ErrorClass::Timeout,
+            Some(io::ErrorKind::ConnectionRefused) => ErrorClass::Refused,
+            Some(
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof,
+            ) => ErrorClass::Reset,
+            Some(io::ErrorKind::TimedOut) => ErrorClass::Timeout,
+            // hyper reports a connection closed mid-response without an io error
+            None if !e.is_connect() && (e.is_request() || e.is_body()) => ErrorClass::Reset,
+            _ => ErrorClass::Other,
+        };
+        Self::new(class, e)
+    }
+}
+
+/// Kind of the innermost io error which caused `e`.
+fn io_error_kind(e: &reqwest::Error) -> Option<io::ErrorKind> {
+    let mut kind = None;
+    let mut source = e.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            kind = Some(e.kind());
+        }
+        source = e.source();
+    }
+    kind
+}
+
+/// Which failed requests are sent again, and when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// attempts after the first one at most
+    pub retries: u32,
+    /// wait before the first retry, doubled for every next one
+    pub backoff: Duration,
+    pub on: Vec<ErrorClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_millis(100),
+            on: vec![
+                ErrorClass::Refused,
+                ErrorClass::Reset,
+                ErrorClass::Timeout,
+                ErrorClass::ServerError,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retrying a request whose `attempt`th try
+    /// (counting from 1) failed with `class`. `None` to give up.
+    pub fn retry(&self, attempt: u32, class: ErrorClass) -> Option<Duration> {
+        if attempt > self.retries || !self.on.contains(&class) {
+            return None;
+        }
+        Some(self.backoff.saturating_mul(1 << (attempt - 1).min(16)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let policy = RetryPolicy {
+            retries: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.retry(1, ErrorClass::Refused),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.retry(2, ErrorClass::Timeout),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(policy.retry(3, ErrorClass::Refused), None);
+        assert_eq!(policy.retry(1, ErrorClass::Parse), None);
+        assert_eq!(RetryPolicy::default().retry(1, ErrorClass::Refused), None);
+    }
+
+    async fn failure(url: String, timeout: Duration) -> Failure {
+        let client = reqwest::Client::builder().timeout(timeout).build().unwrap();
+        client.get(url).send().await.unwrap_err().into()
+    }
+
+    #[tokio::test]
+    async fn test_classify() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        // reads the request, then closes the connection without answering
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0; 1024]).await;
+            drop(stream);
+            // never answers
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0; 1024]).await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+        let timeout = Duration::from_secs(1);
+        assert_eq!(failure(url.clone(), timeout).await.class, ErrorClass::Reset);
+        assert_eq!(
+            failure(url.clone(), Duration::from_millis(100)).await.class,
+            ErrorClass::Timeout
+        );
+        server.abort();
+        let _ = server.await;
+
+        assert_eq!(failure(url, timeout).await.class, ErrorClass::Refused);
+    }
+}
@@ -0,0 +1,13 @@
+//! Load generator for the static site served by the instances: crawls or
+//! replays it at a controlled rate, and records every request.
+
+pub mod connection;
+pub mod crawl;
+pub mod extract;
+pub mod failure;
+pub mod pace;
+pub mod record;
+pub mod replay;
+pub mod scenario;
+pub mod stats;
+pub mod verify;
@@ -1,100 +1,217 @@
-use async_recursion::async_recursion;
-use futures::future::join_all;
-
-const HOST: &str = "http://0.0.0.0:1234";
-
-fn extract_links(body: &str) -> Vec<String> {
-    let dom = tl::parse(body, tl::ParserOptions::default()).unwrap();
-    let parser = dom.parser();
-    // if let Some(anchor) =
-    let mut a_hrefs = dom
-        .query_selector("a[href]")
-        .unwrap()
-        .map(|node| {
-            node.get(parser)
-                .unwrap()
-                .as_tag()
-                .unwrap()
-                .attributes()
-                .get("href")
-                .flatten()
-                .unwrap()
-                .as_utf8_str()
-                .into_owned()
-        })
-        .collect::<Vec<_>>();
-    let mut img_srcs = dom
-        .query_selector("img[src]")
-        .unwrap()
-        .map(|node| {
-            node.get(parser)
-                .unwrap()
-                .as_tag()
-                .unwrap()
-                .attributes()
-                .get("src")
-                .flatten()
-                .unwrap()
-                .as_utf8_str()
-                .into_owned()
-        })
-        .collect::<Vec<_>>();
-
-    a_hrefs.append(&mut img_srcs);
-    a_hrefs
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use clap::Parser;
+use crawler::{
+    connection::{ConnectionMode, HttpVersion, Reuse},
+    crawl::{FanOut, Requester, Resources, Target},
+    failure::{ErrorClass, RetryPolicy},
+    pace::Arrival,
+    record::Recorder,
+    scenario::{Phase, Rate, Scenario, Source},
+    verify::Corpus,
+};
+use reqwest::Url;
+use tokio::time::Instant;
+
+#[derive(Debug, Parser)]
+#[clap(about = "Crawls a site by following its links and loading the assets of its pages")]
+struct Args {
+    /// page to start from
+    #[clap(default_value = "http://0.0.0.0:1234")]
+    target: Url,
+    /// send every request to each of these `<label>=<url>` servers instead,
+    /// starting from the first one's url
+    #[clap(long = "target", multiple_occurrences = true)]
+    targets: Vec<Target>,
+    /// send a request to the targets one after the other, or all at once
+    #[clap(long, arg_enum, default_value = "parallel")]
+    fan_out: FanOut,
+    /// how many links to follow from the target, unlimited by default
+    #[clap(long)]
+    max_depth: Option<usize>,
+    /// stop a pass after fetching this many URLs, unlimited by default
+    #[clap(long)]
+    max_pages: Option<usize>,
+    /// also follow links to other origins than the target's
+    #[clap(long)]
+    cross_origin: bool,
+    /// what to fetch besides the target
+    #[clap(long, arg_enum, default_value = "all")]
+    fetch: Resources,
+    /// requests in flight at most
+    #[clap(long, default_value_t = 16)]
+    concurrency: usize,
+    /// start requests at this rate whatever the response times are,
+    /// instead of as fast as the concurrency allows
+    #[clap(long)]
+    rps: Option<f64>,
+    #[clap(long, arg_enum, default_value = "constant")]
+    arrival: Arrival,
+    /// seed of the Poisson arrivals, random by default
+    #[clap(long)]
+    seed: Option<u64>,
+    /// crawl the site this many times, once by default or until the duration is over
+    #[clap(long)]
+    iterations: Option<usize>,
+    /// keep crawling the site for this many seconds
+    #[clap(long)]
+    duration: Option<u64>,
+    /// send the requests listed in this file instead of following links,
+    /// a JSONL trace if it ends with .jsonl and a URL list otherwise
+    #[clap(long)]
+    replay: Option<PathBuf>,
+    /// send the replayed URLs to the target whatever their origin
+    #[clap(long)]
+    rebase: bool,
+    /// send replayed URLs of other origins than the target's where they
+    /// point; such a file is refused otherwise
+    #[clap(long)]
+    allow_foreign_origins: bool,
+    /// replay a trace this many times faster than it was recorded
+    #[clap(long, default_value_t = 1.0)]
+    speed: f64,
+    /// write every request to this file, as JSONL if it ends with .jsonl and CSV otherwise
+    #[clap(long)]
+    record: Option<PathBuf>,
+    /// open a connection per request, or reuse idle ones
+    #[clap(long, arg_enum, default_value = "keep-alive")]
+    connection: Reuse,
+    /// idle connections kept per host at most with keep-alive, unbounded by default
+    #[clap(long)]
+    pool_size: Option<usize>,
+    #[clap(long, arg_enum, default_value = "1.1")]
+    http_version: HttpVersion,
+    /// give up on a request after this many seconds
+    #[clap(long, default_value_t = 10)]
+    timeout: u64,
+    /// send a failed request again up to this many times
+    #[clap(long, default_value_t = 0)]
+    retries: u32,
+    /// milliseconds before the first retry, doubled for every next one
+    #[clap(long, default_value_t = 100)]
+    retry_backoff: u64,
+    /// failures which are retried
+    #[clap(
+        long,
+        arg_enum,
+        use_value_delimiter = true,
+        default_value = "refused,reset,timeout,server-error"
+    )]
+    retry_on: Vec<ErrorClass>,
+    /// check the bodies of successful responses against the files of this
+    /// directory, e.g. server-contents-setup/static
+    #[clap(long)]
+    verify: Option<PathBuf>,
+    /// run the phases of this TOML file instead of the single one the
+    /// options above describe; the target is used if the file has none,
+    /// and the labelled targets replace the file's
+    #[clap(long)]
+    scenario: Option<PathBuf>,
+    /// width in seconds of the windows of the final summary
+    #[clap(long, default_value_t = 10)]
+    window: u64,
 }
 
-#[async_recursion]
-async fn clawle(url: &str) -> anyhow::Result<()> {
-    let resp = reqwest::get(url).await?;
-
-    tracing::info!("{}: {}", resp.status(), url);
-
-    if let Ok(text) = resp.text().await {
-        let urls = extract_links(&text)
-            .iter()
-            .map(|link| format!("{}/{}", HOST, link))
-            .collect::<Vec<_>>();
-
-        let contents = urls.iter().map(|link| clawle(&link.as_str()));
-
-        join_all(contents)
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
-    };
+impl Args {
+    fn primary_target(&self) -> &Url {
+        self.targets
+            .first()
+            .map_or(&self.target, |target| &target.url)
+    }
 
-    Ok(())
+    /// The scenario of a single phase the options describe.
+    fn scenario(&self) -> Scenario {
+        let source = match &self.replay {
+            Some(path) => Source::Replay {
+                path: path.clone(),
+                rebase: self.rebase,
+                allow_foreign_origins: self.allow_foreign_origins,
+                speed: self.speed,
+            },
+            None => Source::Crawl {
+                max_depth: self.max_depth,
+                max_pages: self.max_pages,
+                cross_origin: self.cross_origin,
+                fetch: self.fetch,
+            },
+        };
+        Scenario {
+            target: Some(self.primary_target().clone()),
+            concurrency: self.concurrency,
+            seed: self.seed,
+            phases: vec![Phase {
+                name: String::new(),
+                duration: self.duration,
+                iterations: self.iterations,
+                rps: self.rps.map(Rate::Fixed),
+                arrival: self.arrival,
+                concurrency: None,
+                source,
+            }],
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt().with_ansi(false).init();
 
-    clawle(HOST).await?;
-
-    Ok(())
-}
+    let args = Args::parse();
+    if args.window == 0 {
+        return Err(anyhow::anyhow!(
+            "--window must be a positive number of seconds"
+        ));
+    }
+    let scenario = match &args.scenario {
+        Some(path) => {
+            let mut scenario = Scenario::load(path)?;
+            if scenario.target.is_none() || !args.targets.is_empty() {
+                scenario.target = Some(args.primary_target().clone());
+            }
+            scenario
+        }
+        None => {
+            let scenario = args.scenario();
+            scenario.validate()?;
+            scenario
+        }
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    let (recorder, recording) = Recorder::spawn(args.record.as_deref())?;
+    let start = Instant::now();
+    let retry = RetryPolicy {
+        retries: args.retries,
+        backoff: Duration::from_millis(args.retry_backoff),
+        on: args.retry_on,
+    };
+    let mode = ConnectionMode {
+        reuse: args.connection,
+        pool_size: args.pool_size,
+        version: args.http_version,
+    };
+    tracing::info!("connections: {}", mode);
+    let mut requester = Requester::new(Duration::from_secs(args.timeout), mode, retry, recorder)?;
+    if let Some(root) = &args.verify {
+        requester = requester.with_corpus(Corpus::new(root)?);
+    }
+    if !args.targets.is_empty() {
+        let targets = args
+            .targets
+            .iter()
+            .map(|t| format!("{}={}", t.label, t.url));
+        tracing::info!("targets: {}", targets.collect::<Vec<_>>().join(", "));
+        requester = requester.with_targets(args.targets.clone(), args.fan_out);
+    }
 
-    #[test]
-    fn test_extract_links() {
-        let links = extract_links(r#"hello <img src="/foo-bar.png"> <a href="/567">good</a>"#);
-        assert_eq!(
-            links,
-            ["/567", "/foo-bar.png"]
-                .into_iter()
-                .map(String::from)
-                .collect::<Vec<_>>()
-        );
+    scenario.run(&requester).await?;
+    let elapsed = start.elapsed();
 
-        let links = extract_links(r#"<img><a>not attrs!</a>"#);
-        assert!(links.is_empty());
+    // the recording ends once the last recorder is dropped
+    drop(requester);
+    let stats = recording.await??;
+    let mut out = std::io::stdout().lock();
+    writeln!(out)?;
+    stats.report(&mut out, elapsed, Duration::from_secs(args.window))?;
 
-        let links = extract_links(r#"no tags!"#);
-        assert!(links.is_empty());
-    }
+    Ok(())
 }
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::time::{sleep_until, Instant};
+
+/// How request start times are spread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Arrival {
+    /// one request every `1 / rps` seconds
+    #[default]
+    Constant,
+    /// exponentially distributed gaps averaging `1 / rps` seconds
+    Poisson,
+}
+
+/// A linear change of the rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ramp {
+    to: f64,
+    over: Duration,
+}
+
+/// Open-loop pacing: start times follow the schedule whatever the response
+/// times are. A pacer which fell behind lets the late requests go at once
+/// instead of shifting the rest of the schedule.
+#[derive(Debug)]
+pub struct Pacer {
+    rps: f64,
+    ramp: Option<Ramp>,
+    arrival: Arrival,
+    rng: StdRng,
+    start: Instant,
+    next: Instant,
+}
+
+impl Pacer {
+    pub fn new(rps: f64, arrival: Arrival, seed: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            rps,
+            ramp: None,
+            arrival,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            start: now,
+            next: now,
+        }
+    }
+
+    /// Moves the rate linearly from the initial one to `rps` over `over`,
+    /// and holds it there afterwards.
+    pub fn ramp_to(mut self, rps: f64, over: Duration) -> Self {
+        self.ramp = Some(Ramp { to: rps, over });
+        self
+    }
+
+    /// The rate at the next start time.
+    fn rps(&self) -> f64 {
+        match self.ramp {
+            Some(ramp) if !ramp.over.is_zero() => {
+                let elapsed = self.next.saturating_duration_since(self.start);
+                let progress = (elapsed.as_secs_f64() / ramp.over.as_secs_f64()).min(1.0);
+                self.rps + (ramp.to - self.rps) * progress
+            }
+            Some(ramp) => ramp.to,
+            None => self.rps,
+        }
+    }
+
+    fn gap(&mut self) -> Duration {
+        let mean = 1.0 / self.rps();
+        match self.arrival {
+            Arrival::Constant => Duration::from_secs_f64(mean),
+            // 1 - u keeps ln away from 0
+            Arrival::Poisson => Duration::from_secs_f64(-(1.0 - self.rng.gen::<f64>()).ln() * mean),
+        }
+    }
+
+    /// Waits for the next scheduled start time.
+    pub async fn wait(&mut self) {
+        sleep_until(self.next).await;
+        let gap = self.gap();
+        self.next += gap;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_gap() {
+        let mut pacer = Pacer::new(4.0, Arrival::Constant, None);
+        assert_eq!(pacer.gap(), Duration::from_millis(250));
+        assert_eq!(pacer.gap(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_poisson_gap() {
+        let mut pacer = Pacer::new(100.0, Arrival::Poisson, Some(42));
+        let n = 10_000;
+        let mean = (0..n).map(|_| pacer.gap().as_secs_f64()).sum::<f64>() / n as f64;
+        assert!((mean - 0.01).abs() < 0.001, "mean gap {}", mean);
+
+        // a seed gives the same schedule every run
+        let gaps = |seed| {
+            let mut pacer = Pacer::new(100.0, Arrival::Poisson, Some(seed));
+            (0..8).map(|_| pacer.gap()).collect::<Vec<_>>()
+        };
+        assert_eq!(gaps(1), gaps(1));
+        assert_ne!(gaps(1), gaps(2));
+    }
+
+    #[test]
+    fn test_ramp() {
+        let mut pacer =
+            Pacer::new(5.0, Arrival::Constant, None).ramp_to(50.0, Duration::from_secs(10));
+        assert_eq!(pacer.gap(), Duration::from_millis(200));
+
+        pacer.next = pacer.start + Duration::from_secs(5);
+        assert!((pacer.rps() - 27.5).abs() < 1e-9);
+
+        pacer.next = pacer.start + Duration::from_secs(60);
+        assert_eq!(pacer.gap(), Duration::from_millis(20));
+    }
+}
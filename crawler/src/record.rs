@@ -0,0 +1,201 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use reqwest::{StatusCode, Url};
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::{
+    connection::{ConnectionMode, HttpVersion, Reuse},
+    failure::{ErrorClass, Failure},
+    stats::{Sample, Stats},
+};
+
+/// What happened to one request.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// wall clock time the request started at
+    pub started_at: OffsetDateTime,
+    pub start: Instant,
+    pub url: Url,
+    /// label of the target the request went to, if there are several
+    pub target: Option<Arc<str>>,
+    /// 1 for the first try of the request, more for its retries
+    pub attempt: u32,
+    pub mode: ConnectionMode,
+    /// `None` when no response arrived
+    pub status: Option<StatusCode>,
+    /// body bytes read
+    pub bytes: u64,
+    /// until the response headers arrived
+    pub ttfb: Option<Duration>,
+    /// until the body was read or the request failed
+    pub latency: Duration,
+    pub failure: Option<Failure>,
+}
+
+impl Exchange {
+    pub fn new(url: Url, target: Option<Arc<str>>, attempt: u32, mode: ConnectionMode) -> Self {
+        Self {
+            started_at: OffsetDateTime::now_utc(),
+            start: Instant::now(),
+            url,
+            target,
+            attempt,
+            mode,
+            status: None,
+            bytes: 0,
+            ttfb: None,
+            latency: Duration::ZERO,
+            failure: None,
+        }
+    }
+}
+
+/// One row of the record file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequestRecord {
+    /// RFC 3339, to line up with the memory series of the instance manager
+    pub start: String,
+    pub target: Option<String>,
+    pub url: String,
+    pub attempt: u32,
+    pub connection: Reuse,
+    pub pool_size: Option<usize>,
+    pub http_version: HttpVersion,
+    pub status: Option<u16>,
+    pub bytes: u64,
+    pub ttfb_ms: Option<f64>,
+    pub latency_ms: f64,
+    pub class: Option<ErrorClass>,
+    pub error: Option<String>,
+}
+
+impl From<Exchange> for RequestRecord {
+    fn from(e: Exchange) -> Self {
+        let ms = |d: Duration| d.as_micros() as f64 / 1000.0;
+        RequestRecord {
+            start: e
+                .started_at
+                .format(&Rfc3339)
+                .unwrap_or_else(|_| e.started_at.to_string()),
+            target: e.target.as_deref().map(String::from),
+            url: e.url.into(),
+            attempt: e.attempt,
+            connection: e.mode.reuse,
+            pool_size: e.mode.pool_size,
+            http_version: e.mode.version,
+            status: e.status.map(|status| status.as_u16()),
+            bytes: e.bytes,
+            ttfb_ms: e.ttfb.map(ms),
+            latency_ms: ms(e.latency),
+            class: e.failure.as_ref().map(|f| f.class),
+            error: e.failure.map(|f| f.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Sink {
+    Csv(Box<csv::Writer<BufWriter<File>>>),
+    Jsonl(BufWriter<File>),
+    Discard,
+}
+
+impl Sink {
+    fn write(&mut self, record: RequestRecord) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(out) => out.serialize(record)?,
+            Sink::Jsonl(out) => {
+                serde_json::to_writer(&mut *out, &record)?;
+                out.write_all(b"\n")?;
+            }
+            Sink::Discard => {}
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Csv(out) => out.flush(),
+            Sink::Jsonl(out) => out.flush(),
+            Sink::Discard => Ok(()),
+        }
+    }
+}
+
+/// Hands finished requests over to the task which writes them and keeps
+/// their samples for the summary.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    /// start of the run, which the sample offsets count from
+    epoch: Instant,
+    sender: UnboundedSender<(Duration, Exchange)>,
+}
+
+impl Recorder {
+    /// Starts the recording task, writing JSONL to `path` if it ends with
+    /// `.jsonl` and CSV otherwise, or nothing without a path.
+    ///
+    /// The task ends with the stats once every recorder is dropped.
+    pub fn spawn(path: Option<&Path>) -> anyhow::Result<(Self, JoinHandle<anyhow::Result<Stats>>)> {
+        let sink = match path {
+            Some(path) => {
+                let out = BufWriter::new(
+                    File::create(path)
+                        .with_context(|| format!("failed to create {}", path.display()))?,
+                );
+                if path.extension().is_some_and(|ext| ext == "jsonl") {
+                    Sink::Jsonl(out)
+                } else {
+                    Sink::Csv(Box::new(csv::Writer::from_writer(out)))
+                }
+            }
+            None => Sink::Discard,
+        };
+        let (sender, receiver) = unbounded_channel();
+        let recorder = Self {
+            epoch: Instant::now(),
+            sender,
+        };
+        Ok((
+            recorder,
+            tokio::task::spawn_blocking(|| collect(sink, receiver)),
+        ))
+    }
+
+    pub fn record(&self, exchange: Exchange) {
+        let offset = exchange.start.saturating_duration_since(self.epoch);
+        // the task only stops early on a write error, which it reports
+        let _ = self.sender.send((offset, exchange));
+    }
+}
+
+fn collect(
+    mut sink: Sink,
+    mut receiver: UnboundedReceiver<(Duration, Exchange)>,
+) -> anyhow::Result<Stats> {
+    let mut stats = Stats::default();
+    while let Some((offset, exchange)) = receiver.blocking_recv() {
+        stats.push(Sample {
+            target: exchange.target.clone(),
+            offset,
+            latency: exchange.latency,
+            class: exchange.failure.as_ref().map(|f| f.class),
+        });
+        sink.write(exchange.into())?;
+    }
+    sink.flush()?;
+    Ok(stats)
+}
@@ -0,0 +1,197 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Context as _;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// One line of a JSONL trace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// seconds since the start of the trace
+    pub at: f64,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRequest {
+    /// when to send the request, relative to the start of the pass
+    pub at: Option<Duration>,
+    pub url: Url,
+}
+
+/// What becomes of replayed URLs on another origin than the target's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForeignUrls {
+    /// the trace is not loaded, so that a list recorded against the
+    /// production site does not send the load there by mistake
+    Refuse,
+    /// they are moved to the target's origin
+    Rebase,
+    /// they are sent where they point
+    Allow,
+}
+
+impl ForeignUrls {
+    /// From the `rebase` and `allow_foreign_origins` options; rebasing wins.
+    pub fn new(rebase: bool, allow: bool) -> Self {
+        match (rebase, allow) {
+            (true, _) => ForeignUrls::Rebase,
+            (false, true) => ForeignUrls::Allow,
+            (false, false) => ForeignUrls::Refuse,
+        }
+    }
+}
+
+/// A fixed sequence of requests, replayed identically on every pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    requests: Vec<TraceRequest>,
+}
+
+impl Trace {
+    /// Reads a JSONL trace if `path` ends with `.jsonl`, and a URL list
+    /// (one URL per line, `#` starts a comment) otherwise.
+    ///
+    /// URLs are resolved against `target`, and those of other origins are
+    /// handled as `foreign` says, so that a list recorded against the
+    /// production site can be replayed against a local server.
+    pub fn load(path: &Path, target: &Url, foreign: ForeignUrls) -> anyhow::Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if path.extension().is_some_and(|ext| ext == "jsonl") {
+            Self::parse_jsonl(&s, target, foreign)
+        } else {
+            Self::parse_list(&s, target, foreign)
+        }
+        .with_context(|| format!("invalid trace {}", path.display()))
+    }
+
+    fn parse_list(s: &str, target: &Url, foreign: ForeignUrls) -> anyhow::Result<Self> {
+        let mut requests = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            requests.push(TraceRequest {
+                at: None,
+                url: resolve(line, target, foreign).with_context(|| format!("line {}", i + 1))?,
+            });
+        }
+        Ok(Self { requests })
+    }
+
+    fn parse_jsonl(s: &str, target: &Url, foreign: ForeignUrls) -> anyhow::Result<Self> {
+        let mut requests = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parse = || -> anyhow::Result<TraceRequest> {
+                let entry: TraceEntry = serde_json::from_str(line)?;
+                if !(entry.at >= 0.0 && entry.at.is_finite()) {
+                    return Err(anyhow::anyhow!(
+                        "`at` must be a non-negative number of seconds"
+                    ));
+                }
+                Ok(TraceRequest {
+                    at: Some(Duration::from_secs_f64(entry.at)),
+                    url: resolve(&entry.url, target, foreign)?,
+                })
+            };
+            requests.push(parse().with_context(|| format!("line {}", i + 1))?);
+        }
+        Ok(Self { requests })
+    }
+
+    pub fn requests(&self) -> &[TraceRequest] {
+        &self.requests
+    }
+}
+
+fn resolve(url: &str, target: &Url, foreign: ForeignUrls) -> anyhow::Result<Url> {
+    let url = target.join(url)?;
+    match foreign {
+        ForeignUrls::Rebase => {
+            let mut rebased = target.clone();
+            rebased.set_path(url.path());
+            rebased.set_query(url.query());
+            Ok(rebased)
+        }
+        ForeignUrls::Refuse if url.origin() != target.origin() => Err(anyhow::anyhow!(
+            "{} is not on the target's origin, rebase it or allow foreign origins",
+            url
+        )),
+        _ => Ok(url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> Url {
+        Url::parse("http://0.0.0.0:1234/").unwrap()
+    }
+
+    fn urls(trace: &Trace) -> Vec<&str> {
+        trace.requests().iter().map(|r| r.url.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let s = "# posts\nhttps://ja.tak-cslab.org/archives/1972\n\n/archives/1994?p=2 # comment\n";
+
+        let err = Trace::parse_list(s, &target(), ForeignUrls::Refuse).unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+
+        let trace = Trace::parse_list(s, &target(), ForeignUrls::Allow).unwrap();
+        assert_eq!(
+            urls(&trace),
+            [
+                "https://ja.tak-cslab.org/archives/1972",
+                "http://0.0.0.0:1234/archives/1994?p=2"
+            ]
+        );
+        assert!(trace.requests().iter().all(|r| r.at.is_none()));
+
+        let trace = Trace::parse_list(s, &target(), ForeignUrls::Rebase).unwrap();
+        assert_eq!(
+            urls(&trace),
+            [
+                "http://0.0.0.0:1234/archives/1972",
+                "http://0.0.0.0:1234/archives/1994?p=2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_jsonl() {
+        let s = r#"{"at": 0, "url": "/"}
+{"at": 0.25, "url": "/archives/1972"}
+"#;
+        let trace = Trace::parse_jsonl(s, &target(), ForeignUrls::Refuse).unwrap();
+        assert_eq!(
+            trace.requests(),
+            [
+                TraceRequest {
+                    at: Some(Duration::ZERO),
+                    url: Url::parse("http://0.0.0.0:1234/").unwrap(),
+                },
+                TraceRequest {
+                    at: Some(Duration::from_millis(250)),
+                    url: Url::parse("http://0.0.0.0:1234/archives/1972").unwrap(),
+                },
+            ]
+        );
+
+        let err = Trace::parse_jsonl(
+            "{\"at\": 0, \"url\": \"/\"}\n{\"at\": -1, \"url\": \"/\"}",
+            &target(),
+            ForeignUrls::Refuse,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+        assert!(Trace::parse_jsonl("/archives/1972", &target(), ForeignUrls::Refuse).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use reqwest::Url;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{
+    crawl::{Crawler, Requester, Resources, Scope},
+    pace::{Arrival, Pacer},
+    replay::{ForeignUrls, Trace},
+};
+
+/// A load test as a sequence of phases, e.g.
+///
+/// ```toml
+/// target = "http://0.0.0.0:1234"
+///
+/// [[phase]]
+/// name = "warmup"
+/// duration = 60
+/// rps = 5
+///
+/// [[phase]]
+/// name = "ramp"
+/// duration = 120
+/// rps = { from = 5, to = 50 }
+/// concurrency = 64
+/// source = { type = "replay", path = "urls.txt", rebase = true }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// page the crawls start from and origin of the replays
+    pub target: Option<Url>,
+    /// requests in flight at most, for phases which do not say
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// seed of the Poisson arrivals, random by default
+    pub seed: Option<u64>,
+    #[serde(rename = "phase")]
+    pub phases: Vec<Phase>,
+}
+
+fn default_concurrency() -> usize {
+    16
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Phase {
+    #[serde(default)]
+    pub name: String,
+    /// seconds the phase lasts
+    pub duration: Option<u64>,
+    /// passes over the source, once by default or until the duration is over
+    pub iterations: Option<usize>,
+    /// open-loop request rate, as fast as the concurrency allows by default
+    pub rps: Option<Rate>,
+    #[serde(default)]
+    pub arrival: Arrival,
+    pub concurrency: Option<usize>,
+    #[serde(default)]
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Rate {
+    Fixed(f64),
+    /// linear over the duration of the phase
+    Ramp {
+        from: f64,
+        to: f64,
+    },
+}
+
+/// Where the URLs of a phase come from.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Source {
+    /// follow the links from the target
+    Crawl {
+        max_depth: Option<usize>,
+        max_pages: Option<usize>,
+        #[serde(default)]
+        cross_origin: bool,
+        #[serde(default = "default_resources")]
+        fetch: Resources,
+    },
+    /// send the requests of a URL list or a JSONL trace
+    Replay {
+        path: PathBuf,
+        /// send the URLs to the target whatever their origin
+        #[serde(default)]
+        rebase: bool,
+        /// send URLs of other origins than the target's where they point,
+        /// instead of refusing the file
+        #[serde(default)]
+        allow_foreign_origins: bool,
+        #[serde(default = "default_speed")]
+        speed: f64,
+    },
+}
+
+fn default_resources() -> Resources {
+    Resources::All
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Crawl {
+            max_depth: None,
+            max_pages: None,
+            cross_origin: false,
+            fetch: default_resources(),
+        }
+    }
+}
+
+impl Phase {
+    fn validate(&self) -> anyhow::Result<()> {
+        let positive = |n: f64| n > 0.0 && n.is_finite();
+        match self.rps {
+            Some(Rate::Fixed(rps)) if !positive(rps) => {
+                return Err(anyhow::anyhow!("rps must be a positive number"));
+            }
+            Some(Rate::Ramp { from, to }) => {
+                if !(positive(from) && positive(to)) {
+                    return Err(anyhow::anyhow!("rps must be positive numbers"));
+                }
+                if self.duration.is_none() {
+                    return Err(anyhow::anyhow!("a ramp needs a duration"));
+                }
+            }
+            _ => {}
+        }
+        if self.concurrency == Some(0) {
+            return Err(anyhow::anyhow!("concurrency must be positive"));
+        }
+        if let Source::Replay { speed, .. } = self.source {
+            if !positive(speed) {
+                return Err(anyhow::anyhow!("speed must be a positive number"));
+            }
+        }
+        Ok(())
+    }
+
+    fn pacer(&self, seed: Option<u64>) -> Option<Pacer> {
+        match self.rps? {
+            Rate::Fixed(rps) => Some(Pacer::new(rps, self.arrival, seed)),
+            Rate::Ramp { from, to } => {
+                let over = Duration::from_secs(self.duration.unwrap_or_default());
+                Some(Pacer::new(from, self.arrival, seed).ramp_to(to, over))
+            }
+        }
+    }
+
+    /// `name pass` for named phases, `pass` otherwise.
+    fn label(&self) -> String {
+        if self.name.is_empty() {
+            "pass".to_string()
+        } else {
+            format!("{} pass", self.name)
+        }
+    }
+}
+
+impl Scenario {
+    /// Reads a TOML scenario. Replayed files are relative to the scenario.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut scenario =
+            Self::parse(&s).with_context(|| format!("invalid scenario {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for phase in &mut scenario.phases {
+            if let Source::Replay { path, .. } = &mut phase.source {
+                *path = dir.join(&*path);
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let scenario: Self = toml::from_str(s)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.concurrency == 0 {
+            return Err(anyhow::anyhow!("concurrency must be positive"));
+        }
+        for (i, phase) in self.phases.iter().enumerate() {
+            phase
+                .validate()
+                .with_context(|| format!("phase {}", i + 1))?;
+        }
+        Ok(())
+    }
+
+    /// Runs the phases one after the other with `requester`.
+    pub async fn run(&self, requester: &Requester) -> anyhow::Result<()> {
+        let target = self.target.as_ref().context("the scenario has no target")?;
+        // a missing file should not stop the run halfway
+        let traces = self
+            .phases
+            .iter()
+            .map(|phase| match &phase.source {
+                Source::Replay {
+                    path,
+                    rebase,
+                    allow_foreign_origins,
+                    ..
+                } => Trace::load(
+                    path,
+                    target,
+                    ForeignUrls::new(*rebase, *allow_foreign_origins),
+                )
+                .map(Some),
+                Source::Crawl { .. } => Ok(None),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for (phase, trace) in self.phases.iter().zip(&traces) {
+            if !phase.name.is_empty() {
+                tracing::info!("phase {}", phase.name);
+            }
+            self.run_phase(target, phase, trace.as_ref(), requester)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn run_phase(
+        &self,
+        target: &Url,
+        phase: &Phase,
+        trace: Option<&Trace>,
+        requester: &Requester,
+    ) {
+        let mut scope = Scope {
+            origin: target.origin(),
+            max_depth: None,
+            max_pages: None,
+            cross_origin: false,
+            resources: default_resources(),
+        };
+        if let Source::Crawl {
+            max_depth,
+            max_pages,
+            cross_origin,
+            fetch,
+        } = phase.source
+        {
+            scope.max_depth = max_depth;
+            scope.max_pages = max_pages;
+            scope.cross_origin = cross_origin;
+            scope.resources = fetch;
+        }
+        let concurrency = phase.concurrency.unwrap_or(self.concurrency);
+        let crawler = Crawler::new(target.clone(), scope, concurrency, requester.clone());
+        let mut pacer = phase.pacer(self.seed);
+
+        let deadline = phase
+            .duration
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        let iterations = match (phase.iterations, deadline) {
+            (Some(n), _) => n,
+            (None, Some(_)) => usize::MAX,
+            (None, None) => 1,
+        };
+
+        for i in 1..=iterations {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            let summary = match (&phase.source, trace) {
+                (Source::Replay { speed, .. }, Some(trace)) => {
+                    crawler
+                        .replay(trace, pacer.as_mut(), *speed, deadline)
+                        .await
+                }
+                _ => crawler.pass(pacer.as_mut(), deadline).await,
+            };
+            tracing::info!("{} {}: {}", phase.label(), i, summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let scenario = Scenario::parse(include_str!("../scenarios/ramp.toml")).unwrap();
+        assert_eq!(
+            scenario.target,
+            Some(Url::parse("http://0.0.0.0:1234").unwrap())
+        );
+        assert_eq!(scenario.phases.len(), 3);
+
+        let warmup = &scenario.phases[0];
+        assert_eq!(warmup.rps, Some(Rate::Fixed(5.0)));
+        assert_eq!(warmup.concurrency, None);
+        assert_eq!(warmup.source, Source::default());
+
+        let ramp = &scenario.phases[1];
+        assert_eq!(
+            ramp.rps,
+            Some(Rate::Ramp {
+                from: 5.0,
+                to: 50.0
+            })
+        );
+        assert_eq!(ramp.duration, Some(120));
+        assert!(matches!(
+            ramp.source,
+            Source::Crawl {
+                fetch: Resources::Pages,
+                ..
+            }
+        ));
+
+        let hold = &scenario.phases[2];
+        assert_eq!(hold.arrival, Arrival::Poisson);
+        assert!(matches!(hold.source, Source::Replay { rebase: true, .. }));
+    }
+
+    #[test]
+    fn test_validate() {
+        let err = |s| Scenario::parse(s).unwrap_err();
+
+        let e = err("[[phase]]\nrps = { from = 5, to = 50 }\n");
+        assert_eq!(e.root_cause().to_string(), "a ramp needs a duration");
+        assert_eq!(e.to_string(), "phase 1");
+
+        let e = err("[[phase]]\n[[phase]]\nrps = -1\n");
+        assert_eq!(e.to_string(), "phase 2");
+
+        assert!(Scenario::parse("[[phase]]\nrps = 5\nsource = { type = \"walk\" }\n").is_err());
+        assert!(Scenario::parse("[[phase]]\nrps = 5\nburst = 3\n").is_err());
+    }
+}
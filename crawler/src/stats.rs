@@ -0,0 +1,248 @@
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+use crate::failure::ErrorClass;
+
+/// What the summary keeps of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// label of the target, if there are several
+    pub target: Option<Arc<str>>,
+    /// start of the request since the start of the run
+    pub offset: Duration,
+    pub latency: Duration,
+    /// `None` for a successful request
+    pub class: Option<ErrorClass>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub count: usize,
+    pub errors: usize,
+    /// the time the requests are spread over, for the throughput
+    pub span: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Summary {
+    fn of(samples: &[Sample], span: Duration) -> Self {
+        let mut latencies = samples.iter().map(|s| s.latency).collect::<Vec<_>>();
+        latencies.sort_unstable();
+        Self {
+            count: samples.len(),
+            errors: samples.iter().filter(|s| s.class.is_some()).count(),
+            span,
+            p50: percentile(&latencies, 50),
+            p90: percentile(&latencies, 90),
+            p99: percentile(&latencies, 99),
+            max: latencies.last().copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.count as f64
+        }
+    }
+
+    pub fn throughput(&self) -> f64 {
+        if self.span.is_zero() {
+            0.0
+        } else {
+            self.count as f64 / self.span.as_secs_f64()
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{:>7} {:>6.2}% {:>9.1} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+            self.count,
+            self.error_rate() * 100.0,
+            self.throughput(),
+            ms(self.p50),
+            ms(self.p90),
+            ms(self.p99),
+            ms(self.max)
+        )
+    }
+}
+
+/// Nearest-rank percentile of sorted `latencies`.
+fn percentile(latencies: &[Duration], p: usize) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p * latencies.len()).div_ceil(100);
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    samples: Vec<Sample>,
+}
+
+impl Stats {
+    pub fn push(&mut self, sample: Sample) {
+        self.samples.push(sample);
+    }
+
+    /// All requests of a run which lasted `elapsed`.
+    pub fn summary(&self, elapsed: Duration) -> Summary {
+        Summary::of(&self.samples, elapsed)
+    }
+
+    /// Failed requests by class.
+    pub fn errors(&self) -> BTreeMap<ErrorClass, usize> {
+        let mut errors = BTreeMap::new();
+        for class in self.samples.iter().filter_map(|s| s.class) {
+            *errors.entry(class).or_default() += 1;
+        }
+        errors
+    }
+
+    /// Requests of a run which lasted `elapsed` by target, for the labelled ones.
+    pub fn targets(&self, elapsed: Duration) -> BTreeMap<Arc<str>, Summary> {
+        let mut targets = BTreeMap::<_, Vec<_>>::new();
+        for sample in &self.samples {
+            if let Some(target) = &sample.target {
+                targets
+                    .entry(target.clone())
+                    .or_default()
+                    .push(sample.clone());
+            }
+        }
+        targets
+            .into_iter()
+            .map(|(target, samples)| (target, Summary::of(&samples, elapsed)))
+            .collect()
+    }
+
+    /// Requests grouped by the `width` long window they started in, with the
+    /// start of each window. Empty windows are kept so that gaps show, and
+    /// the last one only spans until the end of the run.
+    pub fn windows(&self, width: Duration, elapsed: Duration) -> Vec<(Duration, Summary)> {
+        let width = width.max(Duration::from_millis(1));
+        let index = |s: &Sample| (s.offset.as_nanos() / width.as_nanos()) as usize;
+        let n = self.samples.iter().map(|s| index(s) + 1).max().unwrap_or(0);
+
+        let mut windows = vec![Vec::new(); n];
+        for sample in &self.samples {
+            windows[index(sample)].push(sample.clone());
+        }
+        windows
+            .iter()
+            .enumerate()
+            .map(|(i, samples)| {
+                let start = width * i as u32;
+                let span = width.min(elapsed.saturating_sub(start));
+                (start, Summary::of(samples, span))
+            })
+            .collect()
+    }
+
+    /// Writes the overall summary and the one of every window.
+    pub fn report(
+        &self,
+        out: &mut impl std::io::Write,
+        elapsed: Duration,
+        width: Duration,
+    ) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "{:>8} {:>7} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "window", "count", "errors", "req/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        )?;
+        for (start, summary) in self.windows(width, elapsed) {
+            writeln!(out, "{:>7.0}s {}", start.as_secs_f64(), summary)?;
+        }
+        writeln!(out, "{:>8} {}", "total", self.summary(elapsed))?;
+        for (target, summary) in self.targets(elapsed) {
+            writeln!(out, "{:>8} {}", target, summary)?;
+        }
+        for (class, count) in self.errors() {
+            writeln!(out, "{:>8} {:>7}", class, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_percentile() {
+        let latencies = (1..=100).map(ms).collect::<Vec<_>>();
+        assert_eq!(percentile(&latencies, 50), ms(50));
+        assert_eq!(percentile(&latencies, 99), ms(99));
+        assert_eq!(percentile(&[ms(7)], 90), ms(7));
+        assert_eq!(percentile(&[], 90), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut stats = Stats::default();
+        for (offset, latency, class) in [
+            (0, 10, None),
+            (500, 30, Some(ErrorClass::Refused)),
+            (2500, 20, None),
+        ] {
+            stats.push(Sample {
+                target: None,
+                offset: ms(offset),
+                latency: ms(latency),
+                class,
+            });
+        }
+
+        let windows = stats.windows(Duration::from_secs(1), ms(2750));
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0].0, Duration::ZERO);
+        assert_eq!((windows[0].1.count, windows[0].1.errors), (2, 1));
+        assert_eq!(windows[0].1.max, ms(30));
+        assert_eq!(windows[0].1.throughput(), 2.0);
+        assert_eq!(windows[1].1.count, 0);
+        assert_eq!(windows[2].1.p50, ms(20));
+        assert_eq!(windows[2].1.span, ms(750));
+
+        let total = stats.summary(Duration::from_secs(3));
+        assert_eq!(total.count, 3);
+        assert_eq!(total.p50, ms(20));
+        assert!((total.error_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            stats.errors().into_iter().collect::<Vec<_>>(),
+            [(ErrorClass::Refused, 1)]
+        );
+    }
+
+    #[test]
+    fn test_targets() {
+        let mut stats = Stats::default();
+        for (target, latency) in [("wasmedge", 10), ("native", 2), ("wasmedge", 30)] {
+            stats.push(Sample {
+                target: Some(target.into()),
+                offset: Duration::ZERO,
+                latency: ms(latency),
+                class: None,
+            });
+        }
+
+        let targets = stats.targets(Duration::from_secs(1));
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets["native"].count, 1);
+        assert_eq!(targets["wasmedge"].count, 2);
+        assert_eq!(targets["wasmedge"].max, ms(30));
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::failure::{ErrorClass, Failure};
+
+/// What a file of the corpus should be served as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expected {
+    len: usize,
+    digest: [u8; 32],
+}
+
+/// The static files the server serves, to check responses against.
+#[derive(Debug)]
+pub struct Corpus {
+    root: PathBuf,
+    /// files already read, `None` for URLs without a file
+    expected: Mutex<HashMap<PathBuf, Option<Arc<Expected>>>>,
+}
+
+impl Corpus {
+    pub fn new(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        if !root.is_dir() {
+            return Err(anyhow::anyhow!("{} is not a directory", root.display()));
+        }
+        Ok(Self {
+            root,
+            expected: Default::default(),
+        })
+    }
+
+    /// Checks a successful response to `url` against its file. URLs
+    /// without a file are not checked, and the content type only when the
+    /// response has one.
+    pub async fn verify(
+        &self,
+        url: &Url,
+        content_type: Option<&str>,
+        body: &[u8],
+    ) -> Result<(), Failure> {
+        let path = match file_path(&self.root, url) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let expected = match self.expected(&path).await {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        if body.len() < expected.len {
+            return Err(Failure::new(
+                ErrorClass::Truncated,
+                format!("{} of {} bytes", body.len(), expected.len),
+            ));
+        }
+        if body.len() != expected.len || Sha256::digest(body)[..] != expected.digest[..] {
+            return Err(Failure::new(
+                ErrorClass::Mismatch,
+                format!("body differs from {}", path.display()),
+            ));
+        }
+        if let (Some(actual), Some(expected)) = (content_type, content_type_of(&path)) {
+            let essence = actual.split(';').next().unwrap_or_default().trim();
+            if !essence.eq_ignore_ascii_case(expected) {
+                return Err(Failure::new(
+                    ErrorClass::ContentType,
+                    format!("{} instead of {}", essence, expected),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn expected(&self, path: &Path) -> Option<Arc<Expected>> {
+        if let Some(expected) = self.expected.lock().unwrap().get(path) {
+            return expected.clone();
+        }
+        let expected = tokio::fs::read(path).await.ok().map(|content| {
+            Arc::new(Expected {
+                len: content.len(),
+                digest: Sha256::digest(&content).into(),
+            })
+        });
+        self.expected
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), expected.clone());
+        expected
+    }
+}
+
+/// The file under `root` which `url` serves, `index.html` for directories.
+/// `None` for paths which would leave `root`.
+fn file_path(root: &Path, url: &Url) -> Option<PathBuf> {
+    let mut path = root.to_owned();
+    for segment in url.path_segments()? {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match segment.as_ref() {
+            "" | "." => {}
+            ".." => return None,
+            s if s.contains('/') || s.contains('\\') => return None,
+            s => path.push(s),
+        }
+    }
+    // a directory is served by its index with or without the trailing slash
+    if url.path().ends_with('/') || path.is_dir() {
+        path.push("index.html");
+    }
+    Some(path)
+}
+
+/// The content type a file should be served with, by its extension.
+fn content_type_of(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_path() {
+        let root = Path::new("static");
+        let path = |s| file_path(root, &Url::parse(s).unwrap());
+
+        assert_eq!(
+            path("http://0.0.0.0:1234/"),
+            Some(PathBuf::from("static/index.html"))
+        );
+        assert_eq!(
+            path("http://0.0.0.0:1234/archives/1972/img%20a.png?x=1"),
+            Some(PathBuf::from("static/archives/1972/img a.png"))
+        );
+        assert_eq!(
+            path("http://0.0.0.0:1234/archives/1972/"),
+            Some(PathBuf::from("static/archives/1972/index.html"))
+        );
+        assert_eq!(path("http://0.0.0.0:1234/a%2fb"), None);
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("archives/1972")).unwrap();
+        assert_eq!(
+            file_path(
+                root.path(),
+                &Url::parse("http://0.0.0.0:1234/archives/1972").unwrap()
+            ),
+            Some(root.path().join("archives/1972/index.html"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("a.css"), "body {}").unwrap();
+        let corpus = Corpus::new(root.path()).unwrap();
+        let url = Url::parse("http://0.0.0.0:1234/a.css").unwrap();
+        let class = |result: Result<(), Failure>| result.map_err(|f| f.class);
+
+        assert_eq!(
+            class(
+                corpus
+                    .verify(&url, Some("text/css; charset=utf-8"), b"body {}")
+                    .await
+            ),
+            Ok(())
+        );
+        assert_eq!(class(corpus.verify(&url, None, b"body {}").await), Ok(()));
+        assert_eq!(
+            class(corpus.verify(&url, None, b"body").await),
+            Err(ErrorClass::Truncated)
+        );
+        assert_eq!(
+            class(corpus.verify(&url, None, b"body {x}").await),
+            Err(ErrorClass::Mismatch)
+        );
+        assert_eq!(
+            class(corpus.verify(&url, None, b"body ()").await),
+            Err(ErrorClass::Mismatch)
+        );
+        assert_eq!(
+            class(corpus.verify(&url, Some("text/plain"), b"body {}").await),
+            Err(ErrorClass::ContentType)
+        );
+
+        let missing = Url::parse("http://0.0.0.0:1234/missing.css").unwrap();
+        assert_eq!(class(corpus.verify(&missing, None, b"").await), Ok(()));
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Instant,
+};
+
+use tokio::{fs, sync::Mutex};
+
+use crate::domain::{Module, Runtime};
+
+/// Cache of AOT compiled modules.
+///
+/// ```text
+/// <root>/<runtime>/<runtime version>/<digest>.<so|cwasm>
+/// ```
+#[derive(Debug, Clone)]
+pub struct AotCache {
+    root: PathBuf,
+    versions: Arc<Mutex<HashMap<Runtime, String>>>,
+}
+
+impl AotCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            versions: Default::default(),
+        }
+    }
+
+    async fn version(&self, runtime: Runtime) -> anyhow::Result<String> {
+        let mut versions = self.versions.lock().await;
+        if let Some(version) = versions.get(&runtime) {
+            return Ok(version.clone());
+        }
+        let version = runtime.version().await?;
+        versions.insert(runtime, version.clone());
+        Ok(version)
+    }
+
+    fn artifact_path(&self, runtime: Runtime, version: &str, module: &Module) -> PathBuf {
+        self.root.join(runtime.as_str()).join(version).join(format!(
+            "{}.{}",
+            module.digest,
+            runtime.aot_extension()
+        ))
+    }
+
+    /// Path of the AOT artifact of `module`, compiling it on the first use.
+    pub async fn compile(&self, runtime: Runtime, module: &Module) -> anyhow::Result<PathBuf> {
+        let version = self.version(runtime).await?;
+        let path = self.artifact_path(runtime, &version, module);
+        if path.exists() {
+            return Ok(path);
+        }
+
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir).await?;
+        // compile next to the artifact and rename, so that concurrent
+        // compilations of the same module never expose a partial file
+        let tmp = dir.join(format!(
+            ".{}.{}.tmp.{}",
+            module.digest,
+            ulid::Ulid::new(),
+            runtime.aot_extension()
+        ));
+
+        let start = Instant::now();
+        let output = runtime
+            .compile_command(&module.path, &tmp)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(anyhow::anyhow!(
+                "failed to compile {} for {} {}: {}\n{}",
+                module.digest,
+                runtime,
+                version,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        fs::rename(&tmp, &path).await?;
+
+        tracing::info!(
+            "compiled {} for {} {} in {:?}",
+            module.digest,
+            runtime,
+            version,
+            start.elapsed()
+        );
+        Ok(path)
+    }
+}
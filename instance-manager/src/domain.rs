@@ -1,12 +1,25 @@
 use std::marker::PhantomData;
-use tokio::task::{JoinError, JoinHandle};
+use tokio::{
+    sync::oneshot,
+    task::{JoinError, JoinHandle},
+};
 
-pub use instance::{Instance, InstanceId, InstanceManifest};
+pub use health::HealthCheck;
+pub use instance::{Instance, InstanceId, InstanceManifest, InstanceStatus};
 pub use metrics::{InstanceMemoryMetrics, MemoryUsage};
-pub use worker::{Worker, WorkerId, WorkerManifest};
+pub use module::{Module, ModuleDigest, ModuleRef};
+pub use process::{Pid, Process, ProcessInfo};
+pub use restart::{MemorySnapshot, RestartEvent, RestartPolicy, RestartTrigger};
+pub use runtime::{Execution, Runtime};
+pub use worker::{InstanceFactory, Worker, WorkerControl, WorkerId, WorkerManifest};
 
+mod health;
 mod instance;
 mod metrics;
+mod module;
+mod process;
+mod restart;
+mod runtime;
 mod worker;
 
 #[derive(Debug)]
@@ -16,6 +29,7 @@ where
     R: Send + Sync,
 {
     handle: JoinHandle<R>,
+    shutdown: Option<oneshot::Sender<()>>,
     _marker: PhantomData<T>,
 }
 
@@ -27,6 +41,16 @@ where
     pub fn new(handle: JoinHandle<R>) -> Self {
         Self {
             handle,
+            shutdown: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// A handler whose task finishes by itself once `shutdown` is signalled.
+    pub fn with_shutdown(handle: JoinHandle<R>, shutdown: oneshot::Sender<()>) -> Self {
+        Self {
+            handle,
+            shutdown: Some(shutdown),
             _marker: PhantomData,
         }
     }
@@ -35,8 +59,22 @@ where
         self.handle.await
     }
 
-    pub fn stop(&self) {
-        self.handle.abort();
+    /// Waits for the task without giving up the handler. Must not be called
+    /// again once it has returned.
+    pub async fn join(&mut self) -> Result<R, JoinError> {
+        (&mut self.handle).await
+    }
+
+    /// Asks the task to finish and waits for it, aborting it when it cannot be asked.
+    pub async fn shutdown(mut self) -> Result<R, JoinError> {
+        let sent = match self.shutdown.take() {
+            Some(shutdown) => shutdown.send(()).is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.handle.abort();
+        }
+        self.handle.await
     }
 }
 
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout, Instant},
+};
+
+/// HTTP probe deciding whether an instance is ready to serve.
+///
+/// An instance is healthy once `GET <path>` on its port is answered with a
+/// status below 500.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheck {
+    pub path: String,
+    /// give up after this many seconds
+    pub timeout_secs: u64,
+    pub interval_ms: u64,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            timeout_secs: 10,
+            interval_ms: 100,
+        }
+    }
+}
+
+impl HealthCheck {
+    /// Probes `port` until it is healthy and returns how long that took.
+    pub async fn wait_ready(&self, port: u16) -> anyhow::Result<Duration> {
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(self.timeout_secs);
+        loop {
+            let err = match timeout(deadline - Instant::now(), self.probe(port)).await {
+                Ok(Ok(())) => return Ok(start.elapsed()),
+                Ok(Err(e)) => e,
+                Err(_) => anyhow::anyhow!("probe timed out"),
+            };
+            if Instant::now() + Duration::from_millis(self.interval_ms) >= deadline {
+                return Err(err.context(format!(
+                    "port {} is not healthy after {}s",
+                    port, self.timeout_secs
+                )));
+            }
+            sleep(Duration::from_millis(self.interval_ms)).await;
+        }
+    }
+
+    /// Probes `port` once.
+    pub async fn check(&self, port: u16) -> anyhow::Result<()> {
+        timeout(Duration::from_secs(self.timeout_secs), self.probe(port))
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!("port {} did not answer in {}s", port, self.timeout_secs)
+            })?
+    }
+
+    async fn probe(&self, port: u16) -> anyhow::Result<()> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.0\r\nHost: 127.0.0.1:{}\r\n\r\n",
+                    self.path, port
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        let mut buf = [0u8; 64];
+        let mut len = 0;
+        while len < buf.len() && !buf[..len].contains(&b'\n') {
+            match stream.read(&mut buf[len..]).await? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        // drain the rest, closing with unread data would reset the connection
+        tokio::io::copy(&mut stream, &mut tokio::io::sink()).await?;
+
+        let status = parse_status(&buf[..len])
+            .ok_or_else(|| anyhow::anyhow!("malformed response from port {}", port))?;
+        if status < 500 {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("port {} answered {}", port, status))
+        }
+    }
+}
+
+fn parse_status(line: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status(b"HTTP/1.0 200 OK\r\n"), Some(200));
+        assert_eq!(parse_status(b"HTTP/1.1 404 "), Some(404));
+        assert_eq!(parse_status(b"SSH-2.0-OpenSSH"), None);
+        assert_eq!(parse_status(b""), None);
+    }
+
+    async fn serve(status: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(format!("HTTP/1.0 {}\r\n\r\n", status).as_bytes())
+                    .await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready() {
+        let check = HealthCheck {
+            timeout_secs: 1,
+            ..Default::default()
+        };
+
+        assert!(check.wait_ready(serve("200 OK").await).await.is_ok());
+        assert!(check.wait_ready(serve("404 Not Found").await).await.is_ok());
+        assert!(check
+            .wait_ready(serve("500 Internal Server Error").await)
+            .await
+            .is_err());
+    }
+}
@@ -1,9 +1,12 @@
-use std::{process::Output, fmt};
+use std::{fmt, process::ExitStatus, str::FromStr, sync::Arc};
 
-use tokio::{process::Child, signal::ctrl_c};
+use serde::{Deserialize, Serialize};
+use tokio::{signal::ctrl_c, sync::oneshot};
 use ulid::Ulid;
 
-use super::Handler;
+use crate::register::InstanceRegister;
+
+use super::{Execution, Handler, Module, ModuleRef, Pid, Process, Runtime};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct InstanceId(Ulid);
@@ -24,35 +27,128 @@ impl fmt::Display for InstanceId {
     }
 }
 
+impl FromStr for InstanceId {
+    type Err = ulid::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(s.parse()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstanceStatus {
+    Starting,
+    Running,
+    Quit,
+}
+
+impl InstanceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstanceStatus::Starting => "starting",
+            InstanceStatus::Running => "running",
+            InstanceStatus::Quit => "quit",
+        }
+    }
+}
+
+impl fmt::Display for InstanceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for InstanceStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "starting" => Ok(InstanceStatus::Starting),
+            "running" => Ok(InstanceStatus::Running),
+            "quit" => Ok(InstanceStatus::Quit),
+            _ => Err(anyhow::anyhow!("unknown instance status: {}", s)),
+        }
+    }
+}
+
+enum Interrupt {
+    CtrlC,
+    Shutdown,
+}
+
 #[derive(Debug)]
 pub struct Instance {
     pub id: InstanceId,
-    child: Child,
+    pub module: Module,
+    process: Process,
+    /// leave the process running when the manager shuts down
+    detached: bool,
+    register: Arc<dyn InstanceRegister>,
 }
 
 impl Instance {
-    pub fn new(id: InstanceId, child: Child) -> Self {
-        Self { id, child }
+    pub fn new(
+        id: InstanceId,
+        module: Module,
+        process: Process,
+        detached: bool,
+        register: Arc<dyn InstanceRegister>,
+    ) -> Self {
+        Self {
+            id,
+            module,
+            process,
+            detached,
+            register,
+        }
     }
 
-    pub fn spawn(mut self) -> Handler<Self, anyhow::Result<Output>> {
+    pub fn pid(&self) -> Option<Pid> {
+        self.process.pid()
+    }
+
+    /// The exit status is `None` if the instance was left running (detached)
+    /// or is not a child of this process.
+    ///
+    /// Shutting the handler down kills the instance even if it is detached.
+    pub fn spawn(mut self) -> Handler<Self, anyhow::Result<Option<ExitStatus>>> {
+        let (shutdown, stop) = oneshot::channel();
         let handle = tokio::spawn(async move {
             tracing::debug!("Instance {:?} spawn!", self.id);
 
-            ctrl_c().await.ok();
-            self.child.start_kill()?;
-            let output = self.child.wait_with_output().await?;
-            Ok(output)
+            let exited = tokio::select! {
+                status = self.process.wait() => Ok(status?),
+                _ = ctrl_c() => Err(Interrupt::CtrlC),
+                // a dropped handler must not take the instance down with it
+                Ok(()) = stop => Err(Interrupt::Shutdown),
+            };
+            let status = match exited {
+                Ok(status) => status,
+                Err(Interrupt::CtrlC) if self.detached => {
+                    tracing::info!("Instance {} is left running", self.id);
+                    return Ok(None);
+                }
+                Err(_) => self.process.kill().await?,
+            };
+
+            self.register.update(&self.id, InstanceStatus::Quit).await?;
+            Ok(status)
         });
 
-        Handler::new(handle)
+        Handler::with_shutdown(handle, shutdown)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct InstanceManifest {
+    pub module: ModuleRef,
+    /// runtime options placed before the module path
     pub args: Vec<String>,
     pub port: u16,
+    #[serde(default)]
+    pub runtime: Runtime,
+    #[serde(default)]
+    pub execution: Execution,
 }
 
 #[cfg(test)]
@@ -63,4 +159,25 @@ mod tests {
         let id = InstanceId::generate();
         assert_eq!(id.to_string().len(), 26);
     }
+
+    #[test]
+    fn test_instance_id_from_str() {
+        let id = InstanceId::generate();
+        assert_eq!(id.to_string().parse::<InstanceId>().unwrap(), id);
+    }
+
+    #[test]
+    fn test_instance_status_round_trip() {
+        for status in [
+            InstanceStatus::Starting,
+            InstanceStatus::Running,
+            InstanceStatus::Quit,
+        ] {
+            assert_eq!(
+                status.to_string().parse::<InstanceStatus>().unwrap(),
+                status
+            );
+        }
+        assert!("unknown".parse::<InstanceStatus>().is_err());
+    }
 }
@@ -9,7 +9,7 @@ use tokio::{
 
 use crate::repository::CsvInstanceMemoryRepository;
 
-use super::{HostMachine, InstanceId, WorkerId};
+use super::{HostMachine, InstanceId, MemorySnapshot, Pid, WorkerId};
 
 macro_rules! regex {
     ($re:literal $(,)?) => {{
@@ -29,7 +29,15 @@ impl MemoryUsage for Child {
         let pid = self
             .id()
             .ok_or_else(|| anyhow::anyhow!("the child has been polled to completion"))?;
-        let s = tokio::fs::read_to_string(format!("/proc/{}/smaps", pid)).await?;
+        Pid(pid).memory_usage().await
+    }
+}
+
+/// USS of the process, which is what a restart gives back to the host.
+#[async_trait]
+impl MemoryUsage for Pid {
+    async fn memory_usage(&self) -> anyhow::Result<u32> {
+        let s = tokio::fs::read_to_string(format!("/proc/{}/smaps", self)).await?;
         Ok(regex!(r"Private_((Clean)|(Dirty)):\s*(\d+)\skB")
             .captures_iter(&s)
             .map(|cap| cap.get(4).unwrap())
@@ -54,6 +62,19 @@ impl MemoryUsage for HostMachine {
     }
 }
 
+impl MemorySnapshot {
+    pub async fn take(pid: Option<Pid>) -> Self {
+        let instance = match pid {
+            Some(pid) => pid.memory_usage().await.ok(),
+            None => None,
+        };
+        Self {
+            instance,
+            host: HostMachine.memory_usage().await.ok(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct InstanceMemoryMetrics {
     pub timestamp: time::PrimitiveDateTime,
@@ -0,0 +1,157 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// hex encoded SHA-256 of the module file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ModuleDigest(String);
+
+impl ModuleDigest {
+    pub fn new(hex: impl Into<String>) -> Self {
+        Self(hex.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ModuleDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for ModuleDigest {
+    type Err = anyhow::Error;
+
+    /// Accepts the hex digest with or without the `sha256:` prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix(ModuleRef::DIGEST_PREFIX).unwrap_or(s);
+        if hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Ok(Self::new(hex.to_ascii_lowercase()))
+        } else {
+            Err(anyhow::anyhow!("invalid sha256 digest: {}", s))
+        }
+    }
+}
+
+/// How a manifest refers to its module.
+///
+/// Written as `sha256:<hex>` for a digest, `<name>@<tag>` for a tag in the
+/// module store, and anything else is taken as a file path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ModuleRef {
+    Digest(ModuleDigest),
+    Tagged { name: String, tag: String },
+    Path(PathBuf),
+}
+
+impl ModuleRef {
+    pub const DIGEST_PREFIX: &'static str = "sha256:";
+}
+
+impl fmt::Display for ModuleRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleRef::Digest(digest) => write!(f, "{}{}", Self::DIGEST_PREFIX, digest),
+            ModuleRef::Tagged { name, tag } => write!(f, "{}@{}", name, tag),
+            ModuleRef::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for ModuleRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(Self::DIGEST_PREFIX) {
+            return Ok(ModuleRef::Digest(s.parse()?));
+        }
+        match s.split_once('@') {
+            Some((name, tag)) if !s.contains('/') => {
+                if name.is_empty() || tag.is_empty() || tag.contains('@') {
+                    return Err(anyhow::anyhow!("invalid module tag: {}", s));
+                }
+                Ok(ModuleRef::Tagged {
+                    name: name.to_string(),
+                    tag: tag.to_string(),
+                })
+            }
+            _ => Ok(ModuleRef::Path(PathBuf::from(s))),
+        }
+    }
+}
+
+impl TryFrom<String> for ModuleRef {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ModuleRef> for String {
+    fn from(r: ModuleRef) -> Self {
+        r.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    pub path: PathBuf,
+    pub digest: ModuleDigest,
+}
+
+impl Module {
+    pub fn digest_of(data: &[u8]) -> ModuleDigest {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        ModuleDigest::new(format!("{:0x}", hasher.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_digest() {
+        assert_eq!(
+            Module::digest_of(b"hello").as_str(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_module_ref_parse() {
+        let hex = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        for (s, expected) in [
+            (
+                format!("sha256:{}", hex),
+                ModuleRef::Digest(ModuleDigest::new(hex)),
+            ),
+            (
+                "wasmedge-app@v1".to_string(),
+                ModuleRef::Tagged {
+                    name: "wasmedge-app".into(),
+                    tag: "v1".into(),
+                },
+            ),
+            (
+                "../wasmedge-app/target/app@v1.wasm".to_string(),
+                ModuleRef::Path("../wasmedge-app/target/app@v1.wasm".into()),
+            ),
+        ] {
+            let r = s.parse::<ModuleRef>().unwrap();
+            assert_eq!(r, expected);
+            assert_eq!(r.to_string(), s);
+        }
+
+        assert!("sha256:1234".parse::<ModuleRef>().is_err());
+        assert!("@v1".parse::<ModuleRef>().is_err());
+        assert!("wasmedge-app@".parse::<ModuleRef>().is_err());
+    }
+}
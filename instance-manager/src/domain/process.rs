@@ -0,0 +1,195 @@
+use std::{fmt, io, process::ExitStatus, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Child;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Pid(pub u32);
+
+impl Pid {
+    /// Reads the process start time (clock ticks after boot) from `/proc/<pid>/stat`.
+    ///
+    /// A pid may be reused by an unrelated process once the original one exits,
+    /// so the pair of pid and start time is what identifies a process.
+    pub async fn start_time(&self) -> io::Result<u64> {
+        let s = tokio::fs::read_to_string(format!("/proc/{}/stat", self.0)).await?;
+        let stat = ProcessStat::parse(&s)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed stat"))?;
+        if stat.state == 'Z' {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("process {} is a zombie", self.0),
+            ));
+        }
+        Ok(stat.start_time)
+    }
+
+    /// Whether the process that started at `start_time` is still running.
+    pub async fn is_alive_since(&self, start_time: u64) -> bool {
+        matches!(self.start_time().await, Ok(t) if t == start_time)
+    }
+
+    pub fn terminate(&self) -> io::Result<()> {
+        self.signal(libc::SIGTERM)
+    }
+
+    pub fn kill(&self) -> io::Result<()> {
+        self.signal(libc::SIGKILL)
+    }
+
+    fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        // SAFETY: kill(2) has no memory safety requirements.
+        if unsafe { libc::kill(self.0 as libc::pid_t, signal) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// pid and start time of a running instance, as kept in the register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub start_time: u64,
+}
+
+impl ProcessInfo {
+    pub async fn of(pid: Pid) -> io::Result<Self> {
+        Ok(Self {
+            pid,
+            start_time: pid.start_time().await?,
+        })
+    }
+
+    pub async fn is_alive(&self) -> bool {
+        self.pid.is_alive_since(self.start_time).await
+    }
+}
+
+/// The OS process behind an instance.
+///
+/// `Attached` is a process which was started by a previous run of the manager,
+/// so it is not our child and can only be watched through `/proc`.
+#[derive(Debug)]
+pub enum Process {
+    Child(Child),
+    Attached(ProcessInfo),
+}
+
+impl Process {
+    const ATTACHED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+    /// how long an attached process has to exit after SIGTERM before it gets SIGKILL
+    const ATTACHED_KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Waits for the process to exit. The exit status is unknown for attached processes.
+    pub async fn wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            Process::Child(child) => child.wait().await.map(Some),
+            Process::Attached(info) => {
+                while info.is_alive().await {
+                    tokio::time::sleep(Self::ATTACHED_POLL_INTERVAL).await;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn pid(&self) -> Option<Pid> {
+        match self {
+            Process::Child(child) => child.id().map(Pid),
+            Process::Attached(info) => Some(info.pid),
+        }
+    }
+
+    pub async fn kill(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.kill_within(Self::ATTACHED_KILL_TIMEOUT).await
+    }
+
+    /// Kills a child at once, and an attached process with SIGTERM first,
+    /// escalating to SIGKILL once it has not exited within `timeout`.
+    async fn kill_within(&mut self, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+        let info = match self {
+            Process::Child(child) => {
+                child.start_kill()?;
+                return self.wait().await;
+            }
+            Process::Attached(info) => *info,
+        };
+        info.pid.terminate()?;
+        if let Ok(status) = tokio::time::timeout(timeout, self.wait()).await {
+            return status;
+        }
+        tracing::warn!("process {} ignored SIGTERM, sending SIGKILL", info.pid);
+        // the start time is checked again so that a reused pid is left alone
+        if info.is_alive().await {
+            info.pid.kill()?;
+        }
+        self.wait().await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ProcessStat {
+    state: char,
+    start_time: u64,
+}
+
+impl ProcessStat {
+    fn parse(s: &str) -> Option<Self> {
+        // `comm` is wrapped in parentheses and may itself contain spaces or ')'
+        let (_, rest) = s.rsplit_once(')')?;
+        let mut fields = rest.split_whitespace();
+        let state = fields.next()?.chars().next()?;
+        // starttime is the 22nd field, 19 fields after state
+        let start_time = fields.nth(18)?.parse().ok()?;
+        Some(Self { state, start_time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_kill_attached_ignoring_sigterm() {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "trap '' TERM; while :; do sleep 1; done"])
+            .spawn()
+            .unwrap();
+        let info = ProcessInfo::of(Pid(child.id())).await.unwrap();
+        let mut process = Process::Attached(info);
+
+        let killed = process.kill_within(Duration::from_millis(200));
+        let result = tokio::time::timeout(Duration::from_secs(5), killed).await;
+        assert!(matches!(result, Ok(Ok(None))));
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let s = "1234 (wasm edge) S 1 1234 1234 0 -1 4194560 1024 0 0 0 10 5 0 0 20 0 4 0 987654 123456789 2048 18446744073709551615";
+        assert_eq!(
+            ProcessStat::parse(s),
+            Some(ProcessStat {
+                state: 'S',
+                start_time: 987654
+            })
+        );
+        assert_eq!(ProcessStat::parse("1234 (truncated) S 1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_own_start_time() {
+        let pid = Pid(std::process::id());
+        let start_time = pid.start_time().await.unwrap();
+        assert!(pid.is_alive_since(start_time).await);
+        assert!(!pid.is_alive_since(start_time + 1).await);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{InstanceId, WorkerId};
+
+/// Why a worker replaced its instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestartTrigger {
+    /// the instance used more memory than `RestartPolicy::memory_threshold_kb`
+    Threshold,
+    /// the instance stopped answering its health check
+    Health,
+    /// an operator asked for it, including rollouts
+    Manual,
+    /// the instance reached `RestartPolicy::interval_secs`
+    Schedule,
+}
+
+impl RestartTrigger {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartTrigger::Threshold => "threshold",
+            RestartTrigger::Health => "health",
+            RestartTrigger::Manual => "manual",
+            RestartTrigger::Schedule => "schedule",
+        }
+    }
+}
+
+impl fmt::Display for RestartTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RestartTrigger {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "threshold" => Ok(RestartTrigger::Threshold),
+            "health" => Ok(RestartTrigger::Health),
+            "manual" => Ok(RestartTrigger::Manual),
+            "schedule" => Ok(RestartTrigger::Schedule),
+            _ => Err(anyhow::anyhow!("unknown restart trigger: {}", s)),
+        }
+    }
+}
+
+/// When a worker restarts its instance by itself. Everything is off by default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartPolicy {
+    /// restart once the USS of the instance exceeds this many kB
+    pub memory_threshold_kb: Option<u32>,
+    /// restart once the instance has run this many seconds
+    pub interval_secs: Option<u64>,
+    /// restart when the health check fails
+    pub on_unhealthy: bool,
+    /// how often the conditions above are checked
+    pub check_interval_ms: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            memory_threshold_kb: None,
+            interval_secs: None,
+            on_unhealthy: false,
+            check_interval_ms: 1000,
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.memory_threshold_kb.is_some() || self.interval_secs.is_some() || self.on_unhealthy
+    }
+}
+
+/// Memory in kB, `None` when it could not be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemorySnapshot {
+    /// USS of the instance
+    pub instance: Option<u32>,
+    /// used memory of the host
+    pub host: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartEvent {
+    pub timestamp: OffsetDateTime,
+    pub worker_id: WorkerId,
+    pub old_instance_id: InstanceId,
+    /// `None` when no instance could be created
+    pub new_instance_id: Option<InstanceId>,
+    pub trigger: RestartTrigger,
+    pub before: MemorySnapshot,
+    /// taken once the new instance is ready, or has failed to become ready
+    pub after: MemorySnapshot,
+    /// from stopping the old instance until the new one is ready, or has failed to become ready
+    pub downtime: Duration,
+    /// from spawning the new instance until it is ready, `None` when it never was
+    pub spawn_to_ready: Option<Duration>,
+    /// why the restart failed, `None` when it succeeded
+    pub error: Option<String>,
+}
+
+impl RestartEvent {
+    pub fn outcome(&self) -> &'static str {
+        match self.error {
+            None => "ok",
+            Some(_) => "failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_trigger_round_trip() {
+        for trigger in [
+            RestartTrigger::Threshold,
+            RestartTrigger::Health,
+            RestartTrigger::Manual,
+            RestartTrigger::Schedule,
+        ] {
+            assert_eq!(
+                trigger.to_string().parse::<RestartTrigger>().unwrap(),
+                trigger
+            );
+        }
+        assert!("cron".parse::<RestartTrigger>().is_err());
+    }
+
+    #[test]
+    fn test_restart_event_outcome() {
+        let mut event = RestartEvent {
+            timestamp: OffsetDateTime::UNIX_EPOCH,
+            worker_id: WorkerId::generate(),
+            old_instance_id: InstanceId::generate(),
+            new_instance_id: Some(InstanceId::generate()),
+            trigger: RestartTrigger::Health,
+            before: MemorySnapshot::default(),
+            after: MemorySnapshot::default(),
+            downtime: Duration::from_millis(30),
+            spawn_to_ready: Some(Duration::from_millis(20)),
+            error: None,
+        };
+        assert_eq!(event.outcome(), "ok");
+
+        event.spawn_to_ready = None;
+        event.error = Some("instance exited before becoming healthy".to_string());
+        assert_eq!(event.outcome(), "failed");
+    }
+}
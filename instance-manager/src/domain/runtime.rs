@@ -0,0 +1,163 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use super::InstanceManifest;
+
+/// The wasm runtime an instance runs on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    #[default]
+    Wasmedge,
+    Wasmtime,
+}
+
+impl Runtime {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Runtime::Wasmedge => "wasmedge",
+            Runtime::Wasmtime => "wasmtime",
+        }
+    }
+
+    /// File extension of the runtime's AOT artifacts.
+    pub fn aot_extension(&self) -> &'static str {
+        match self {
+            Runtime::Wasmedge => "so",
+            Runtime::Wasmtime => "cwasm",
+        }
+    }
+
+    /// Version of the installed runtime, e.g. `0.9.1`.
+    ///
+    /// AOT artifacts only run on the version which compiled them, so the
+    /// version is part of the cache key.
+    pub async fn version(&self) -> anyhow::Result<String> {
+        let output = Command::new(self.as_str())
+            .arg("--version")
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "`{} --version` failed: {}",
+                self,
+                output.status
+            ));
+        }
+        parse_version(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| anyhow::anyhow!("unknown {} version", self))
+    }
+
+    /// Command compiling the module at `input` to an AOT artifact at `output`.
+    pub fn compile_command(&self, input: &Path, output: &Path) -> Command {
+        let mut command = match self {
+            Runtime::Wasmedge => Command::new("wasmedgec"),
+            Runtime::Wasmtime => {
+                let mut command = Command::new("wasmtime");
+                command.arg("compile").arg("-o");
+                command
+            }
+        };
+        match self {
+            Runtime::Wasmedge => command.arg(input).arg(output),
+            Runtime::Wasmtime => command.arg(output).arg(input),
+        };
+        command
+    }
+
+    /// Command running `module`, which is an AOT artifact when `aot` is set.
+    pub fn run_command(&self, man: &InstanceManifest, module: &Path, aot: bool) -> Command {
+        let mut command = Command::new(self.as_str());
+        match self {
+            Runtime::Wasmedge => {}
+            Runtime::Wasmtime => {
+                // wasmtime does not pass the host environment to the guest
+                command
+                    .arg("run")
+                    .arg("--env")
+                    .arg(format!("PORT={}", man.port));
+                if aot {
+                    command.arg("--allow-precompiled");
+                }
+            }
+        }
+        command
+            .args(&man.args)
+            .arg(module)
+            .env("PORT", man.port.to_string().as_str());
+        command
+    }
+}
+
+impl fmt::Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Runtime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wasmedge" => Ok(Runtime::Wasmedge),
+            "wasmtime" => Ok(Runtime::Wasmtime),
+            _ => Err(anyhow::anyhow!("unknown runtime: {}", s)),
+        }
+    }
+}
+
+/// How the runtime executes a module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Execution {
+    #[default]
+    Interpreted,
+    /// run a precompiled native artifact, compiled once per module and runtime version
+    Aot,
+}
+
+impl FromStr for Execution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreted" => Ok(Execution::Interpreted),
+            "aot" => Ok(Execution::Aot),
+            _ => Err(anyhow::anyhow!("unknown execution mode: {}", s)),
+        }
+    }
+}
+
+/// Picks the version number out of `--version` output such as
+/// `wasmedge version 0.9.1` or `wasmtime-cli 0.37.0`.
+fn parse_version(s: &str) -> Option<String> {
+    s.split_whitespace()
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+        .filter(|word| {
+            word.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+' | '_'))
+        })
+        .map(|word| word.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("wasmedge version 0.9.1\n").as_deref(),
+            Some("0.9.1")
+        );
+        assert_eq!(
+            parse_version("wasmtime-cli 0.37.0").as_deref(),
+            Some("0.37.0")
+        );
+        assert_eq!(parse_version("wasmedge version").as_deref(), None);
+        assert_eq!(parse_version("evil 1.0/../..").as_deref(), None);
+    }
+}
@@ -1,42 +1,413 @@
-use std::{process::Output, fmt};
+use std::{fmt, process::ExitStatus, str::FromStr, sync::Arc, time::Duration};
 
-use tokio::signal::ctrl_c;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinError,
+    time::{interval, Instant, MissedTickBehavior},
+};
 use ulid::Ulid;
 
-use super::{Handler, Instance, InstanceManifest};
+use crate::{register::InstanceRegister, repository::RestartEventRepository};
+
+use super::{
+    Handler, HealthCheck, Instance, InstanceId, InstanceManifest, MemorySnapshot, MemoryUsage,
+    Module, ModuleRef, Pid, RestartEvent, RestartPolicy, RestartTrigger,
+};
+
+type InstanceResult = anyhow::Result<Option<ExitStatus>>;
+
+/// Creates instances on behalf of a worker, so that the worker can replace its instance.
+#[async_trait]
+pub trait InstanceFactory: fmt::Debug + Send + Sync + 'static {
+    async fn create(
+        &self,
+        man: &InstanceManifest,
+        worker_id: WorkerId,
+        detached: bool,
+    ) -> anyhow::Result<Instance>;
+}
 
+#[derive(Debug)]
+enum WorkerOp {
+    Update {
+        manifest: WorkerManifest,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
+    Restart {
+        trigger: RestartTrigger,
+        reply: oneshot::Sender<anyhow::Result<()>>,
+    },
+    Manifest(oneshot::Sender<WorkerManifest>),
+}
+
+/// The worker's view of its latest instance.
+#[derive(Debug, Clone)]
+struct Running {
+    id: InstanceId,
+    module: Module,
+    pid: Option<Pid>,
+    since: Instant,
+}
+
+impl Running {
+    fn of(instance: &Instance) -> Self {
+        Self {
+            id: instance.id,
+            module: instance.module.clone(),
+            pid: instance.pid(),
+            since: Instant::now(),
+        }
+    }
+}
+
+/// Keeps one instance running for a manifest.
 #[derive(Debug)]
 pub struct Worker {
     pub id: WorkerId,
-    pub instance_handler: Handler<Instance, anyhow::Result<Output>>,
+    manifest: WorkerManifest,
+    running: Running,
+    instance: Option<Handler<Instance, InstanceResult>>,
+    factory: Arc<dyn InstanceFactory>,
+    register: Arc<dyn InstanceRegister>,
+    events: RestartEventRepository,
     // pub metrics_collect_handler: Handler<>
 }
 
 impl Worker {
-    pub fn new(id: WorkerId, instance_handler: Handler<Instance, anyhow::Result<Output>>) -> Self {
+    pub fn new(
+        id: WorkerId,
+        manifest: WorkerManifest,
+        instance: Instance,
+        factory: Arc<dyn InstanceFactory>,
+        register: Arc<dyn InstanceRegister>,
+        events: RestartEventRepository,
+    ) -> Self {
         Self {
             id,
-            instance_handler,
+            manifest,
+            running: Running::of(&instance),
+            instance: Some(instance.spawn()),
+            factory,
+            register,
+            events,
+        }
+    }
+
+    pub fn spawn(self) -> WorkerHandler {
+        let id = self.id;
+        let (sender, receiver) = mpsc::channel(8);
+        let handle = tokio::spawn(self.run(receiver));
+
+        WorkerHandler {
+            id,
+            sender,
+            handler: Handler::new(handle),
+        }
+    }
+
+    async fn run(mut self, mut ops: mpsc::Receiver<WorkerOp>) -> InstanceResult {
+        tracing::debug!("Worker {:?} spawn!", self.id);
+
+        let mut checks = interval(Duration::from_millis(
+            self.manifest.restart.check_interval_ms.max(1),
+        ));
+        checks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let instance = match self.instance.as_mut() {
+                Some(instance) => instance,
+                None => {
+                    // nothing could be started again after a failed update or
+                    // restart; a detached worker is respawned from its manifest on
+                    // the next run
+                    if !self.manifest.detached {
+                        self.register.deregister_worker(&self.id).await?;
+                    }
+                    return Err(anyhow::anyhow!("worker {} lost its instance", self.id));
+                }
+            };
+            let op = tokio::select! {
+                result = instance.join() => {
+                    self.instance = None;
+                    let status = result??;
+                    // a detached worker stays registered so that the next run reattaches to it
+                    if !self.manifest.detached {
+                        self.register.deregister_worker(&self.id).await?;
+                    }
+                    return Ok(status);
+                }
+                Some(op) = ops.recv() => Some(op),
+                _ = checks.tick(), if self.manifest.restart.is_enabled() => None,
+            };
+
+            match op {
+                Some(WorkerOp::Update { manifest, reply }) => {
+                    let result = self.update(manifest).await;
+                    let _ = reply.send(result);
+                }
+                Some(WorkerOp::Restart { trigger, reply }) => {
+                    let result = self.restart(trigger).await;
+                    let _ = reply.send(result);
+                }
+                Some(WorkerOp::Manifest(reply)) => {
+                    let _ = reply.send(self.pinned_manifest());
+                }
+                None => {
+                    if let Some(trigger) = self.restart_due().await {
+                        if let Err(e) = self.restart(trigger).await {
+                            tracing::error!("worker {} failed to restart: {:#}", self.id, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The current manifest, referring to the module by the digest of what the
+    /// instance runs, so that it still means the same module after a tag moves.
+    fn pinned_manifest(&self) -> WorkerManifest {
+        let mut manifest = self.manifest.clone();
+        manifest.instance_manifest.module = ModuleRef::Digest(self.running.module.digest.clone());
+        manifest
+    }
+
+    /// Checks the restart policy, in the order schedule, memory threshold, health.
+    async fn restart_due(&self) -> Option<RestartTrigger> {
+        let policy = &self.manifest.restart;
+        if let Some(secs) = policy.interval_secs {
+            if self.running.since.elapsed() >= Duration::from_secs(secs) {
+                return Some(RestartTrigger::Schedule);
+            }
+        }
+        if let (Some(threshold), Some(pid)) = (policy.memory_threshold_kb, self.running.pid) {
+            if matches!(pid.memory_usage().await, Ok(kb) if kb > threshold) {
+                return Some(RestartTrigger::Threshold);
+            }
+        }
+        if policy.on_unhealthy {
+            let port = self.manifest.instance_manifest.port;
+            if let Err(e) = self.manifest.health_check.check(port).await {
+                tracing::warn!("worker {} is unhealthy: {:#}", self.id, e);
+                return Some(RestartTrigger::Health);
+            }
+        }
+        None
+    }
+
+    async fn stop(&mut self) -> anyhow::Result<()> {
+        if let Some(instance) = self.instance.take() {
+            instance.shutdown().await??;
+        }
+        Ok(())
+    }
+
+    /// Starts an instance from `manifest` and returns how long it took to become healthy.
+    async fn start(&mut self, manifest: &WorkerManifest) -> anyhow::Result<Duration> {
+        let instance = self
+            .factory
+            .create(&manifest.instance_manifest, self.id, manifest.detached)
+            .await?;
+        self.running = Running::of(&instance);
+        let instance = self.instance.insert(instance.spawn());
+        tokio::select! {
+            ready = manifest.health_check.wait_ready(manifest.instance_manifest.port) => ready,
+            result = instance.join() => {
+                self.instance = None;
+                match result?? {
+                    Some(status) => Err(anyhow::anyhow!("instance exited before becoming healthy: {}", status)),
+                    None => Err(anyhow::anyhow!("instance exited before becoming healthy")),
+                }
+            }
         }
     }
 
-    pub fn spawn(self) -> Handler<Self, anyhow::Result<Output>> {
-        let handle = tokio::spawn(async move {
-            tracing::debug!("Worker {:?} spawn!", self.id);
-            ctrl_c().await.ok();
+    /// Replaces the instance with one created from `manifest` and records the
+    /// restart, whether or not the new instance becomes healthy.
+    async fn replace(
+        &mut self,
+        manifest: &WorkerManifest,
+        trigger: RestartTrigger,
+    ) -> anyhow::Result<()> {
+        let old = self.running.clone();
+        let before = MemorySnapshot::take(old.pid).await;
+
+        let stopped_at = Instant::now();
+        // the old instance is gone either way, so a new one is started regardless
+        if let Err(e) = self.stop().await {
+            tracing::warn!(
+                "worker {} failed to stop instance {}: {:#}",
+                self.id,
+                old.id,
+                e
+            );
+        }
+        let result = self.start(manifest).await;
+        let downtime = stopped_at.elapsed();
+        let new_instance_id = Some(self.running.id).filter(|id| *id != old.id);
 
-            self.instance_handler.stop();
-            let result = self.instance_handler.wait().await??;
-            Ok(result)
-        });
+        let event = RestartEvent {
+            timestamp: OffsetDateTime::now_utc(),
+            worker_id: self.id,
+            old_instance_id: old.id,
+            new_instance_id,
+            trigger,
+            before,
+            after: MemorySnapshot::take(new_instance_id.and(self.running.pid)).await,
+            downtime,
+            spawn_to_ready: result.as_ref().ok().copied(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        };
+        match &result {
+            Ok(_) => tracing::info!(
+                "worker {} restarted ({}): {} -> {}, down for {:?}",
+                self.id,
+                trigger,
+                old.id,
+                self.running.id,
+                downtime
+            ),
+            Err(e) => tracing::warn!(
+                "worker {} failed to restart ({}) from {}: {:#}",
+                self.id,
+                trigger,
+                old.id,
+                e
+            ),
+        }
+        if let Err(e) = self.events.store(event).await {
+            tracing::warn!("failed to record restart of worker {}: {:#}", self.id, e);
+        }
+        result.map(|_| ())
+    }
 
-        Handler::new(handle)
+    /// Replaces the instance with a fresh one of the same module, trying once
+    /// more when the fresh one does not become healthy.
+    async fn restart(&mut self, trigger: RestartTrigger) -> anyhow::Result<()> {
+        let manifest = self.pinned_manifest();
+        if let Err(e) = self.replace(&manifest, trigger).await {
+            tracing::warn!("worker {} retries its restart", self.id);
+            self.replace(&manifest, trigger).await.map_err(|retry| {
+                anyhow::anyhow!("{:#}; retrying failed as well: {:#}", e, retry)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the instance with one created from `manifest`. If the new
+    /// instance does not become healthy, the previous module is started again.
+    async fn update(&mut self, manifest: WorkerManifest) -> anyhow::Result<()> {
+        let previous = self.pinned_manifest();
+        tracing::info!(
+            "updating worker {}: {} -> {}",
+            self.id,
+            previous.instance_manifest.module,
+            manifest.instance_manifest.module
+        );
+
+        match self.replace(&manifest, RestartTrigger::Manual).await {
+            Ok(()) => {
+                self.register.update_worker(&self.id, &manifest).await?;
+                self.manifest = manifest;
+                Ok(())
+            }
+            Err(e) => {
+                tracing::warn!("worker {} failed to update: {:#}", self.id, e);
+                match self.restore(&previous).await {
+                    Ok(()) => Err(e),
+                    Err(restore) => Err(anyhow::anyhow!(
+                        "{:#}; restoring the previous instance failed as well: {:#}",
+                        e,
+                        restore
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Discards what is left of a failed replacement and starts an instance
+    /// from `manifest` again.
+    async fn restore(&mut self, manifest: &WorkerManifest) -> anyhow::Result<()> {
+        if let Err(e) = self.stop().await {
+            tracing::warn!("worker {} failed to stop its instance: {:#}", self.id, e);
+        }
+        self.start(manifest).await?;
+        Ok(())
     }
 }
 
+/// Handle to a spawned `Worker`.
 #[derive(Debug)]
+pub struct WorkerHandler {
+    pub id: WorkerId,
+    sender: mpsc::Sender<WorkerOp>,
+    handler: Handler<Worker, InstanceResult>,
+}
+
+impl WorkerHandler {
+    pub fn control(&self) -> WorkerControl {
+        WorkerControl {
+            id: self.id,
+            sender: self.sender.clone(),
+        }
+    }
+
+    pub async fn wait(self) -> Result<InstanceResult, JoinError> {
+        self.handler.wait().await
+    }
+}
+
+/// Sends operations to a spawned `Worker` while someone else waits for it.
+#[derive(Debug, Clone)]
+pub struct WorkerControl {
+    pub id: WorkerId,
+    sender: mpsc::Sender<WorkerOp>,
+}
+
+impl WorkerControl {
+    /// Replaces the instance with a fresh one of the same module.
+    pub async fn restart(&self, trigger: RestartTrigger) -> anyhow::Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(WorkerOp::Restart { trigger, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("worker {} has stopped", self.id))?;
+        result.await?
+    }
+
+    /// Moves the worker to `manifest`; see `Worker::update`.
+    pub async fn update(&self, manifest: WorkerManifest) -> anyhow::Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(WorkerOp::Update { manifest, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("worker {} has stopped", self.id))?;
+        result.await?
+    }
+
+    /// The manifest the worker currently runs, pinned to its module digest.
+    pub async fn manifest(&self) -> anyhow::Result<WorkerManifest> {
+        let (reply, result) = oneshot::channel();
+        self.sender
+            .send(WorkerOp::Manifest(reply))
+            .await
+            .map_err(|_| anyhow::anyhow!("worker {} has stopped", self.id))?;
+        Ok(result.await?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct WorkerManifest {
     pub instance_manifest: InstanceManifest,
+    /// keep the instance running across manager restarts
+    #[serde(default)]
+    pub detached: bool,
+    #[serde(default)]
+    pub health_check: HealthCheck,
+    #[serde(default)]
+    pub restart: RestartPolicy,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -58,6 +429,14 @@ impl fmt::Display for WorkerId {
     }
 }
 
+impl FromStr for WorkerId {
+    type Err = ulid::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(s.parse()?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,4 +1,7 @@
-use std::{fs::File, io::BufWriter};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
 
 use csv::Writer;
 use serde::Serialize;
@@ -36,6 +39,8 @@ where
 
     fn store(&mut self, data: T) -> anyhow::Result<()> {
         self.out.serialize(data)?;
+        // the manager may be killed at any time, do not keep rows in the buffer
+        self.out.flush()?;
         Ok(())
     }
 }
@@ -46,3 +51,46 @@ impl<T: Serialize + Send + Sync> Drop for CsvExportDriver<T> {
         self.reciver.close();
     }
 }
+
+/// Writes one JSON object per line.
+#[derive(Debug)]
+pub struct JsonlExportDriver<T>
+where
+    T: Serialize + Send + Sync + 'static,
+{
+    out: BufWriter<File>,
+    reciver: Receiver<T>,
+}
+
+impl<T> JsonlExportDriver<T>
+where
+    T: Serialize + Send + Sync + 'static,
+{
+    pub fn new(out: BufWriter<File>, reciver: Receiver<T>) -> Self {
+        Self { out, reciver }
+    }
+
+    pub fn spawn(mut self) -> JoinHandle<anyhow::Result<()>> {
+        tokio::task::spawn_blocking(move || loop {
+            if let Some(data) = self.reciver.blocking_recv() {
+                self.store(data)?;
+            } else {
+                break Ok(());
+            }
+        })
+    }
+
+    fn store(&mut self, data: T) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.out, &data)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<T: Serialize + Send + Sync> Drop for JsonlExportDriver<T> {
+    fn drop(&mut self) {
+        self.out.flush().unwrap();
+        self.reciver.close();
+    }
+}
@@ -1,15 +1,137 @@
-use std::{fs::File, io::BufWriter};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use domain::{InstanceManifest, WorkerManifest};
-use driver::CsvExportDriver;
-use repository::CsvInstanceMemoryRepository;
-use tokio::{signal::ctrl_c, sync::mpsc};
+use aot::AotCache;
+use clap::{Parser, Subcommand};
+use domain::{
+    Execution, HealthCheck, InstanceManifest, ModuleDigest, ModuleRef, RestartPolicy, Runtime,
+    WorkerControl, WorkerManifest,
+};
+use driver::{CsvExportDriver, JsonlExportDriver};
+use repository::{CsvInstanceMemoryRepository, RestartEventRepository};
+use store::ModuleStore;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+};
 use tracing::Level;
 
+mod aot;
 mod domain;
 mod driver;
+mod register;
 mod repository;
 mod service;
+mod store;
+
+#[derive(Debug, Parser)]
+#[clap(about = "Keeps WasmEdge instances running")]
+struct Cli {
+    /// directory holding imported modules
+    #[clap(long, env = "MODULE_STORE", default_value = "modules", global = true)]
+    module_store: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the workers (the default)
+    Run(RunArgs),
+    /// Copy a module into the store and print its digest
+    Import {
+        path: PathBuf,
+        /// also point NAME@TAG at the module
+        #[clap(long)]
+        tag: Option<ModuleRef>,
+    },
+    /// Point NAME@TAG at a module in the store
+    Tag {
+        name: ModuleRef,
+        digest: ModuleDigest,
+    },
+    /// List the tags of NAME
+    Tags { name: String },
+    /// Compile a module ahead of time, so that the first AOT run does not wait for it
+    Compile {
+        module: ModuleRef,
+        #[clap(long, default_value = "wasmedge")]
+        runtime: Runtime,
+    },
+}
+
+#[derive(Debug, Parser)]
+struct RunArgs {
+    /// module path, NAME@TAG or sha256:DIGEST
+    #[clap(
+        long,
+        default_value = "../wasmedge-app/target/wasm32-wasi/release/wasmedge-app.wasm"
+    )]
+    module: ModuleRef,
+    /// port of the first worker, the others listen on the following ports
+    #[clap(long, default_value_t = 1234)]
+    port: u16,
+    #[clap(long, default_value_t = 1)]
+    replicas: u16,
+    /// wasmedge or wasmtime
+    #[clap(long, env = "RUNTIME", default_value = "wasmedge")]
+    runtime: Runtime,
+    /// interpreted or aot
+    #[clap(long, env = "EXECUTION", default_value = "interpreted")]
+    execution: Execution,
+    /// keep the instances running when the manager exits
+    #[clap(long, env = "DETACHED")]
+    detached: bool,
+    /// restart an instance once its USS exceeds this many kB
+    #[clap(long)]
+    restart_threshold_kb: Option<u32>,
+    /// restart every instance after this many seconds
+    #[clap(long)]
+    restart_interval_secs: Option<u64>,
+    /// restart an instance when its health check fails
+    #[clap(long)]
+    restart_on_unhealthy: bool,
+    /// restart events are written here, as JSON lines if the file ends with .jsonl and CSV otherwise
+    #[clap(long, env = "RESTART_LOG", default_value = "restart_events.csv")]
+    restart_log: PathBuf,
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self::parse_from(["run"])
+    }
+}
+
+/// Runtime options serving `server-contents-setup/static` as the working directory.
+fn default_args(runtime: Runtime) -> Vec<String> {
+    let args: &[&str] = match runtime {
+        Runtime::Wasmedge => &["--dir", ".:../server-contents-setup/static", "--enable-all"],
+        Runtime::Wasmtime => &["--mapdir", ".::../server-contents-setup/static"],
+    };
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+fn restart_event_repository(path: &Path) -> anyhow::Result<RestartEventRepository> {
+    let (send, recv) = mpsc::channel(16);
+    let writer = BufWriter::new(File::create(path)?);
+    if path.extension().is_some_and(|ext| ext == "jsonl") {
+        JsonlExportDriver::new(writer, recv).spawn();
+    } else {
+        CsvExportDriver::new(writer, recv).spawn();
+    }
+    Ok(RestartEventRepository::new(send))
+}
+
+fn split_tag(r: ModuleRef) -> anyhow::Result<(String, String)> {
+    match r {
+        ModuleRef::Tagged { name, tag } => Ok((name, tag)),
+        r => Err(anyhow::anyhow!("expected NAME@TAG, got {}", r)),
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -18,6 +140,38 @@ async fn main() -> anyhow::Result<()> {
         .with_max_level(Level::DEBUG)
         .init();
 
+    let cli = Cli::parse();
+    let store = ModuleStore::new(&cli.module_store);
+    let aot = AotCache::new(cli.module_store.join("aot"));
+    let args = match cli.command {
+        Some(Command::Run(args)) => args,
+        None => RunArgs::default(),
+        Some(Command::Import { path, tag }) => {
+            let module = store.import(&path).await?;
+            if let Some(tag) = tag {
+                let (name, tag) = split_tag(tag)?;
+                store.tag(&name, &tag, &module.digest).await?;
+            }
+            println!("{}", ModuleRef::Digest(module.digest));
+            return Ok(());
+        }
+        Some(Command::Tag { name, digest }) => {
+            let (name, tag) = split_tag(name)?;
+            return store.tag(&name, &tag, &digest).await;
+        }
+        Some(Command::Compile { module, runtime }) => {
+            let module = store.resolve(&module).await?;
+            println!("{}", aot.compile(runtime, &module).await?.display());
+            return Ok(());
+        }
+        Some(Command::Tags { name }) => {
+            for (tag, digest) in store.tags(&name).await? {
+                println!("{}@{}\t{}", name, tag, ModuleRef::Digest(digest));
+            }
+            return Ok(());
+        }
+    };
+
     let (im_send, im_recv) = mpsc::channel(16);
     let im_writer = BufWriter::new(File::create("instance_memory.csv")?);
     let im_exporter = CsvExportDriver::new(im_writer, im_recv).spawn();
@@ -26,27 +180,90 @@ async fn main() -> anyhow::Result<()> {
     let repo = CsvInstanceMemoryRepository::new(im_send);
 
     let hm_writer = BufWriter::new(File::create("host_memory.csv")?);
-    let instance_manifest = InstanceManifest {
-        args: [
-            "--dir",
-            ".:../server-contents-setup/static",
-            "--enable-all",
-            "../wasmedge-app/target/wasm32-wasi/release/wasmedge-app.wasm",
-        ]
-        .iter()
-        .map(|s| s.to_string())
-        .collect::<Vec<_>>(),
-        port: 1234,
-    };
 
-    let worker_man = WorkerManifest { instance_manifest };
-    let worker = service::worker_create_service(&worker_man).await;
-    let handler = worker.spawn();
+    let register = register::open(
+        &std::env::var("INSTANCE_REGISTER")
+            .unwrap_or_else(|_| "sqlite://instance-manager.db".to_string()),
+    )?;
+
+    let ctx = Arc::new(service::Context {
+        register,
+        store,
+        aot,
+        events: restart_event_repository(&args.restart_log)?,
+        log_dir: std::env::var("INSTANCE_LOG_DIR")
+            .unwrap_or_else(|_| "logs".to_string())
+            .into(),
+    });
+
+    let mut workers = service::worker_recover_service(&ctx).await?;
+    if workers.is_empty() {
+        for i in 0..args.replicas {
+            let worker_man = WorkerManifest {
+                instance_manifest: InstanceManifest {
+                    module: args.module.clone(),
+                    args: default_args(args.runtime),
+                    port: args.port + i,
+                    runtime: args.runtime,
+                    execution: args.execution,
+                },
+                detached: args.detached,
+                health_check: HealthCheck::default(),
+                restart: RestartPolicy {
+                    memory_threshold_kb: args.restart_threshold_kb,
+                    interval_secs: args.restart_interval_secs,
+                    on_unhealthy: args.restart_on_unhealthy,
+                    ..Default::default()
+                },
+            };
+            workers.push(service::worker_create_service(&worker_man, &ctx).await?);
+        }
+    }
+    let handlers = workers.into_iter().map(|w| w.spawn()).collect::<Vec<_>>();
 
-    ctrl_c().await.ok();
+    let controls = handlers.iter().map(|h| h.control()).collect::<Vec<_>>();
+    tokio::spawn(read_commands(controls, ctx));
 
-    handler.stop();
-    handler.wait().await??;
+    // every instance stops (or detaches) on ctrl-c by itself; a worker which
+    // fails leaves the others running
+    let total = handlers.len();
+    let mut failed = 0;
+    for handler in handlers {
+        let id = handler.id;
+        if let Err(e) = handler.wait().await.map_err(anyhow::Error::from).and_then(|r| r) {
+            tracing::error!("worker {} failed: {:#}", id, e);
+            failed += 1;
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        n => Err(anyhow::anyhow!("{} of {} workers failed", n, total)),
+    }
+}
 
-    Ok(())
+/// Reads operator commands from stdin while the workers run.
+///
+/// `rollout <module>` moves every worker to `<module>`, `restart [worker id]`
+/// restarts one or every worker.
+async fn read_commands(workers: Vec<WorkerControl>, ctx: Arc<service::Context>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let mut words = line.split_whitespace();
+        let result = match (words.next(), words.next()) {
+            (Some("rollout"), Some(module)) => match module.parse() {
+                Ok(module) => service::pool_rollout_service(&workers, &module, &ctx).await,
+                Err(e) => Err(e),
+            },
+            (Some("restart"), id) => match id.map(str::parse).transpose() {
+                Ok(id) => service::pool_restart_service(&workers, id).await,
+                Err(e) => Err(anyhow::anyhow!("invalid worker id: {}", e)),
+            },
+            (None, _) => continue,
+            _ => Err(anyhow::anyhow!("unknown command: {}", line.trim())),
+        };
+        if let Err(e) = result {
+            tracing::error!("{:#}", e);
+        }
+    }
 }
@@ -0,0 +1,143 @@
+use std::{
+    fmt::{self, Debug},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::domain::{
+    InstanceId, InstanceStatus, Module, ModuleDigest, ProcessInfo, WorkerId, WorkerManifest,
+};
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisRegister;
+pub use self::sqlite::SqliteRegister;
+
+#[cfg(feature = "redis")]
+mod redis;
+mod sqlite;
+
+/// Keeps track of every instance the manager has started, so that the record
+/// survives the manager process itself.
+#[async_trait]
+pub trait InstanceRegister: Debug + Send + Sync + 'static {
+    async fn register(&self, entry: &InstanceEntry) -> anyhow::Result<()>;
+    async fn update(&self, id: &InstanceId, status: InstanceStatus) -> anyhow::Result<()>;
+    /// Instances in the order they were registered. Those which have quit are
+    /// left out unless `include_stopped`.
+    async fn list(&self, include_stopped: bool) -> anyhow::Result<Vec<InstanceEntry>>;
+    /// Status changes of the instance `id`, oldest first.
+    async fn history(&self, id: &InstanceId) -> anyhow::Result<Vec<StatusChange>>;
+    async fn set_process(&self, id: &InstanceId, process: ProcessInfo) -> anyhow::Result<()>;
+
+    async fn register_worker(&self, entry: &WorkerEntry) -> anyhow::Result<()>;
+    async fn update_worker(&self, id: &WorkerId, manifest: &WorkerManifest) -> anyhow::Result<()>;
+    /// Marks the worker as stopped on purpose, so it is not recovered on the next start.
+    async fn deregister_worker(&self, id: &WorkerId) -> anyhow::Result<()>;
+    /// Workers which have not been deregistered.
+    async fn list_workers(&self) -> anyhow::Result<Vec<WorkerEntry>>;
+    /// Records why the worker could not be recovered, or clears it with `None`.
+    async fn set_worker_error(&self, id: &WorkerId, error: Option<&str>) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceEntry {
+    pub worker_id: WorkerId,
+    pub instance_id: InstanceId,
+    pub module_digest: ModuleDigest,
+    pub module_path: PathBuf,
+    pub status: InstanceStatus,
+    pub process: Option<ProcessInfo>,
+    pub created_at: OffsetDateTime,
+}
+
+impl InstanceEntry {
+    pub fn new(worker_id: WorkerId, instance_id: InstanceId, module: &Module) -> Self {
+        Self {
+            worker_id,
+            instance_id,
+            module_digest: module.digest.clone(),
+            module_path: module.path.clone(),
+            status: InstanceStatus::Starting,
+            process: None,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerEntry {
+    pub worker_id: WorkerId,
+    pub manifest: WorkerManifest,
+    pub created_at: OffsetDateTime,
+    /// why the last attempt to recover the worker failed
+    pub error: Option<String>,
+}
+
+impl WorkerEntry {
+    pub fn new(worker_id: WorkerId, manifest: &WorkerManifest) -> Self {
+        Self {
+            worker_id,
+            manifest: manifest.clone(),
+            created_at: OffsetDateTime::now_utc(),
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusChange {
+    pub status: InstanceStatus,
+    pub at: OffsetDateTime,
+}
+
+impl fmt::Display for StatusChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.at.format(&Rfc3339) {
+            Ok(at) => write!(f, "{} since {}", self.status, at),
+            Err(_) => write!(f, "{} since {}", self.status, self.at),
+        }
+    }
+}
+
+/// Opens the register described by `url`.
+///
+/// `sqlite://<path>` (or `sqlite::memory:`) selects the embedded store and
+/// `redis://...` the Redis one, which needs the `redis` feature.
+pub fn open(url: &str) -> anyhow::Result<Arc<dyn InstanceRegister>> {
+    if url == "sqlite::memory:" {
+        Ok(Arc::new(SqliteRegister::open_in_memory()?))
+    } else if let Some(path) = url.strip_prefix("sqlite://") {
+        Ok(Arc::new(SqliteRegister::open(path)?))
+    } else if url.starts_with("redis://") {
+        open_redis(url)
+    } else {
+        Err(anyhow::anyhow!("unsupported instance register: {}", url))
+    }
+}
+
+#[cfg(feature = "redis")]
+fn open_redis(url: &str) -> anyhow::Result<Arc<dyn InstanceRegister>> {
+    Ok(Arc::new(RedisRegister::new(url)?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn open_redis(url: &str) -> anyhow::Result<Arc<dyn InstanceRegister>> {
+    Err(anyhow::anyhow!(
+        "{} requires instance-manager to be built with the `redis` feature",
+        url
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        assert!(open("sqlite::memory:").is_ok());
+        assert!(open("memcached://0.0.0.0:11211").is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use async_trait::async_trait;
+use r2d2::Pool;
+use redis::{Client, Commands};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::domain::{
+    InstanceId, InstanceStatus, ModuleDigest, Pid, ProcessInfo, WorkerId, WorkerManifest,
+};
+
+use super::{InstanceEntry, InstanceRegister, StatusChange, WorkerEntry};
+
+/// `InstanceRegister` backed by a Redis server.
+///
+/// Each instance is a hash at `instance:<id>`, its status history a list at
+/// `instance:<id>:status` and the set `instances` indexes all of them.
+/// Workers are hashes at `worker:<id>`, and the set `workers` holds the ones
+/// which have not been deregistered.
+#[derive(Debug, Clone)]
+pub struct RedisRegister {
+    pool: Pool<Client>,
+}
+
+impl RedisRegister {
+    const PREFIX: &'static str = "instance:";
+    const INDEX: &'static str = "instances";
+    const WORKER_PREFIX: &'static str = "worker:";
+    const WORKER_INDEX: &'static str = "workers";
+
+    pub fn new(params: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            pool: Pool::builder().build(Client::open(params)?)?,
+        })
+    }
+
+    fn to_key(id: &str) -> String {
+        format!("{}{}", Self::PREFIX, id)
+    }
+
+    fn to_worker_key(id: &str) -> String {
+        format!("{}{}", Self::WORKER_PREFIX, id)
+    }
+
+    fn to_history_key(id: &str) -> String {
+        format!("{}{}:status", Self::PREFIX, id)
+    }
+
+    fn encode_change(status: InstanceStatus, at: &OffsetDateTime) -> anyhow::Result<String> {
+        Ok(format!("{}@{}", status, at.format(&Rfc3339)?))
+    }
+
+    fn decode_change(s: &str) -> anyhow::Result<StatusChange> {
+        let (status, at) = s
+            .split_once('@')
+            .ok_or_else(|| anyhow::anyhow!("malformed status change: {}", s))?;
+        Ok(StatusChange {
+            status: status.parse()?,
+            at: OffsetDateTime::parse(at, &Rfc3339)?,
+        })
+    }
+
+    fn find_blocking(conn: &mut impl Commands, id: &str) -> anyhow::Result<Option<InstanceEntry>> {
+        let fields: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(Self::to_key(id))
+            .arg(&[
+                "worker_id",
+                "module_digest",
+                "module_path",
+                "status",
+                "created_at",
+                "pid",
+                "start_time",
+            ])
+            .query(conn)?;
+
+        match fields.as_slice() {
+            [Some(worker_id), Some(module_digest), Some(module_path), Some(status), Some(created_at), pid, start_time] => {
+                Ok(Some(InstanceEntry {
+                    worker_id: worker_id.parse()?,
+                    instance_id: id.parse()?,
+                    module_digest: ModuleDigest::new(module_digest.as_str()),
+                    module_path: module_path.into(),
+                    status: status.parse()?,
+                    process: match (pid, start_time) {
+                        (Some(pid), Some(start_time)) => Some(ProcessInfo {
+                            pid: Pid(pid.parse()?),
+                            start_time: start_time.parse()?,
+                        }),
+                        _ => None,
+                    },
+                    created_at: OffsetDateTime::parse(created_at, &Rfc3339)?,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl InstanceRegister for RedisRegister {
+    async fn register(&self, entry: &InstanceEntry) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = entry.instance_id.to_string();
+        let fields = vec![
+            ("worker_id", entry.worker_id.to_string()),
+            ("module_digest", entry.module_digest.to_string()),
+            (
+                "module_path",
+                entry.module_path.to_string_lossy().into_owned(),
+            ),
+            ("status", entry.status.to_string()),
+            ("created_at", entry.created_at.format(&Rfc3339)?),
+        ];
+        let change = Self::encode_change(entry.status, &entry.created_at)?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            redis::pipe()
+                .atomic()
+                .hset_multiple(Self::to_key(&id), &fields)
+                .rpush(Self::to_history_key(&id), change)
+                .sadd(Self::INDEX, &id)
+                .query::<()>(&mut *conn)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn update(&self, id: &InstanceId, status: InstanceStatus) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = id.to_string();
+        let change = Self::encode_change(status, &OffsetDateTime::now_utc())?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            if !conn.sismember(Self::INDEX, &id)? {
+                return Err(anyhow::anyhow!("instance {} is not registered", id));
+            }
+            redis::pipe()
+                .atomic()
+                .hset(Self::to_key(&id), "status", status.to_string())
+                .rpush(Self::to_history_key(&id), change)
+                .query::<()>(&mut *conn)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn list(&self, include_stopped: bool) -> anyhow::Result<Vec<InstanceEntry>> {
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            let ids: Vec<String> = conn.smembers(Self::INDEX)?;
+            let mut entries = Vec::with_capacity(ids.len());
+            for id in ids {
+                match Self::find_blocking(&mut *conn, &id)? {
+                    Some(entry) if include_stopped || entry.status != InstanceStatus::Quit => {
+                        entries.push(entry)
+                    }
+                    _ => {}
+                }
+            }
+            entries.sort_by_key(|e| e.created_at);
+            Ok(entries)
+        })
+        .await?
+    }
+
+    async fn history(&self, id: &InstanceId) -> anyhow::Result<Vec<StatusChange>> {
+        let pool = self.pool.clone();
+        let id = id.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            let changes: Vec<String> = conn.lrange(Self::to_history_key(&id), 0, -1)?;
+            changes.iter().map(|s| Self::decode_change(s)).collect()
+        })
+        .await?
+    }
+
+    async fn set_process(&self, id: &InstanceId, process: ProcessInfo) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = id.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            if !conn.sismember(Self::INDEX, &id)? {
+                return Err(anyhow::anyhow!("instance {} is not registered", id));
+            }
+            conn.hset_multiple::<_, _, _, ()>(
+                Self::to_key(&id),
+                &[
+                    ("pid", process.pid.0.to_string()),
+                    ("start_time", process.start_time.to_string()),
+                ],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn register_worker(&self, entry: &WorkerEntry) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = entry.worker_id.to_string();
+        let fields = vec![
+            ("manifest", serde_json::to_string(&entry.manifest)?),
+            ("created_at", entry.created_at.format(&Rfc3339)?),
+        ];
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            redis::pipe()
+                .atomic()
+                .hset_multiple(Self::to_worker_key(&id), &fields)
+                .sadd(Self::WORKER_INDEX, &id)
+                .query::<()>(&mut *conn)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn update_worker(&self, id: &WorkerId, manifest: &WorkerManifest) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = id.to_string();
+        let manifest = serde_json::to_string(manifest)?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            if !conn.exists(Self::to_worker_key(&id))? {
+                return Err(anyhow::anyhow!("worker {} is not registered", id));
+            }
+            conn.hset::<_, _, _, ()>(Self::to_worker_key(&id), "manifest", manifest)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn deregister_worker(&self, id: &WorkerId) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = id.to_string();
+        let stopped_at = OffsetDateTime::now_utc().format(&Rfc3339)?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            redis::pipe()
+                .atomic()
+                .hset(Self::to_worker_key(&id), "stopped_at", stopped_at)
+                .srem(Self::WORKER_INDEX, &id)
+                .query::<()>(&mut *conn)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn list_workers(&self) -> anyhow::Result<Vec<WorkerEntry>> {
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            let ids: Vec<String> = conn.smembers(Self::WORKER_INDEX)?;
+            let mut workers = Vec::with_capacity(ids.len());
+            for id in ids {
+                let (manifest, created_at, error): (
+                    Option<String>,
+                    Option<String>,
+                    Option<String>,
+                ) = redis::cmd("HMGET")
+                    .arg(Self::to_worker_key(&id))
+                    .arg(&["manifest", "created_at", "error"])
+                    .query(&mut *conn)?;
+                if let (Some(manifest), Some(created_at)) = (manifest, created_at) {
+                    workers.push(WorkerEntry {
+                        worker_id: id.parse()?,
+                        manifest: serde_json::from_str(&manifest)?,
+                        created_at: OffsetDateTime::parse(&created_at, &Rfc3339)?,
+                        error,
+                    });
+                }
+            }
+            workers.sort_by_key(|w| w.created_at);
+            Ok(workers)
+        })
+        .await?
+    }
+
+    async fn set_worker_error(&self, id: &WorkerId, error: Option<&str>) -> anyhow::Result<()> {
+        let pool = self.pool.clone();
+        let id = id.to_string();
+        let error = error.map(str::to_string);
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get()?;
+            if !conn.exists(Self::to_worker_key(&id))? {
+                return Err(anyhow::anyhow!("worker {} is not registered", id));
+            }
+            match error {
+                Some(error) => {
+                    conn.hset::<_, _, _, ()>(Self::to_worker_key(&id), "error", error)?
+                }
+                None => conn.hdel::<_, _, ()>(Self::to_worker_key(&id), "error")?,
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_change_encoding() {
+        let at = OffsetDateTime::now_utc();
+        let s = RedisRegister::encode_change(InstanceStatus::Running, &at).unwrap();
+        assert_eq!(
+            RedisRegister::decode_change(&s).unwrap(),
+            StatusChange {
+                status: InstanceStatus::Running,
+                at
+            }
+        );
+        assert!(RedisRegister::decode_change("running").is_err());
+    }
+}
@@ -0,0 +1,555 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, Row};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::domain::{
+    InstanceId, InstanceStatus, ModuleDigest, Pid, ProcessInfo, WorkerId, WorkerManifest,
+};
+
+use super::{InstanceEntry, InstanceRegister, StatusChange, WorkerEntry};
+
+/// Schema changes, applied in order. `PRAGMA user_version` records how many
+/// of them a database has already seen.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE instances (
+        instance_id   TEXT PRIMARY KEY,
+        worker_id     TEXT NOT NULL,
+        module_digest TEXT NOT NULL,
+        module_path   TEXT NOT NULL,
+        status        TEXT NOT NULL,
+        created_at    TEXT NOT NULL
+    );
+    CREATE TABLE instance_status (
+        instance_id TEXT NOT NULL REFERENCES instances(instance_id),
+        status      TEXT NOT NULL,
+        at          TEXT NOT NULL
+    );
+    ",
+    "
+    ALTER TABLE instances ADD COLUMN pid INTEGER;
+    ALTER TABLE instances ADD COLUMN start_time INTEGER;
+    CREATE TABLE workers (
+        worker_id  TEXT PRIMARY KEY,
+        manifest   TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        stopped_at TEXT
+    );
+    ",
+    "
+    ALTER TABLE workers ADD COLUMN error TEXT;
+    ",
+];
+
+/// `InstanceRegister` backed by an embedded SQLite database.
+#[derive(Debug, Clone)]
+pub struct SqliteRegister {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteRegister {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> anyhow::Result<Self> {
+        Self::migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn migrate(conn: &mut Connection) -> anyhow::Result<()> {
+        let mut version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        // registers written before the migrations were introduced have the
+        // tables of the first one, but no version
+        if version == 0 && Self::has_table(conn, "instances")? {
+            conn.pragma_update(None, "user_version", 1)?;
+            version = 1;
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    fn has_table(conn: &Connection, name: &str) -> anyhow::Result<bool> {
+        let n: usize = conn.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            params![name],
+            |row| row.get(0),
+        )?;
+        Ok(n > 0)
+    }
+
+    async fn with_conn<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| anyhow::anyhow!("sqlite connection is poisoned"))?;
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+fn format_time(t: &OffsetDateTime) -> anyhow::Result<String> {
+    Ok(t.format(&Rfc3339)?)
+}
+
+fn parse_time(s: &str) -> anyhow::Result<OffsetDateTime> {
+    Ok(OffsetDateTime::parse(s, &Rfc3339)?)
+}
+
+fn entry_from_row(row: &Row) -> anyhow::Result<InstanceEntry> {
+    Ok(InstanceEntry {
+        instance_id: row.get::<_, String>(0)?.parse()?,
+        worker_id: row.get::<_, String>(1)?.parse()?,
+        module_digest: ModuleDigest::new(row.get::<_, String>(2)?),
+        module_path: row.get::<_, String>(3)?.into(),
+        status: row.get::<_, String>(4)?.parse()?,
+        created_at: parse_time(&row.get::<_, String>(5)?)?,
+        process: match (row.get::<_, Option<u32>>(6)?, row.get::<_, Option<i64>>(7)?) {
+            (Some(pid), Some(start_time)) => Some(ProcessInfo {
+                pid: Pid(pid),
+                start_time: start_time as u64,
+            }),
+            _ => None,
+        },
+    })
+}
+
+const SELECT_ENTRY: &str = "SELECT instance_id, worker_id, module_digest, module_path, status, created_at, pid, start_time FROM instances";
+
+#[async_trait]
+impl InstanceRegister for SqliteRegister {
+    async fn register(&self, entry: &InstanceEntry) -> anyhow::Result<()> {
+        let entry = entry.clone();
+        self.with_conn(move |conn| {
+            let created_at = format_time(&entry.created_at)?;
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO instances (instance_id, worker_id, module_digest, module_path, status, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.instance_id.to_string(),
+                    entry.worker_id.to_string(),
+                    entry.module_digest.as_str(),
+                    entry.module_path.to_string_lossy(),
+                    entry.status.as_str(),
+                    created_at,
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO instance_status (instance_id, status, at) VALUES (?1, ?2, ?3)",
+                params![
+                    entry.instance_id.to_string(),
+                    entry.status.as_str(),
+                    created_at
+                ],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update(&self, id: &InstanceId, status: InstanceStatus) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let at = format_time(&OffsetDateTime::now_utc())?;
+            let tx = conn.transaction()?;
+            let n = tx.execute(
+                "UPDATE instances SET status = ?2 WHERE instance_id = ?1",
+                params![id, status.as_str()],
+            )?;
+            if n == 0 {
+                return Err(anyhow::anyhow!("instance {} is not registered", id));
+            }
+            tx.execute(
+                "INSERT INTO instance_status (instance_id, status, at) VALUES (?1, ?2, ?3)",
+                params![id, status.as_str(), at],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self, include_stopped: bool) -> anyhow::Result<Vec<InstanceEntry>> {
+        self.with_conn(move |conn| {
+            // by rowid: RFC 3339 text does not sort by time, as `time` leaves out
+            // trailing zeros of the fraction
+            let mut stmt = conn.prepare(&format!(
+                "{} WHERE ?1 OR status != ?2 ORDER BY rowid",
+                SELECT_ENTRY
+            ))?;
+            let entries = stmt
+                .query_map(
+                    params![include_stopped, InstanceStatus::Quit.as_str()],
+                    |row| Ok(entry_from_row(row)),
+                )?
+                .map(|r| r?)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(entries)
+        })
+        .await
+    }
+
+    async fn history(&self, id: &InstanceId) -> anyhow::Result<Vec<StatusChange>> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT status, at FROM instance_status WHERE instance_id = ?1 ORDER BY rowid",
+            )?;
+            let history = stmt
+                .query_map(params![id], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .map(|r| {
+                    let (status, at) = r?;
+                    Ok(StatusChange {
+                        status: status.parse()?,
+                        at: parse_time(&at)?,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(history)
+        })
+        .await
+    }
+
+    async fn set_process(&self, id: &InstanceId, process: ProcessInfo) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let n = conn.execute(
+                "UPDATE instances SET pid = ?2, start_time = ?3 WHERE instance_id = ?1",
+                params![id, process.pid.0, process.start_time as i64],
+            )?;
+            if n == 0 {
+                return Err(anyhow::anyhow!("instance {} is not registered", id));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn register_worker(&self, entry: &WorkerEntry) -> anyhow::Result<()> {
+        let id = entry.worker_id.to_string();
+        let manifest = serde_json::to_string(&entry.manifest)?;
+        let created_at = format_time(&entry.created_at)?;
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO workers (worker_id, manifest, created_at) VALUES (?1, ?2, ?3)",
+                params![id, manifest, created_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_worker(&self, id: &WorkerId, manifest: &WorkerManifest) -> anyhow::Result<()> {
+        let id = id.to_string();
+        let manifest = serde_json::to_string(manifest)?;
+        self.with_conn(move |conn| {
+            let n = conn.execute(
+                "UPDATE workers SET manifest = ?2 WHERE worker_id = ?1",
+                params![id, manifest],
+            )?;
+            if n == 0 {
+                return Err(anyhow::anyhow!("worker {} is not registered", id));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn deregister_worker(&self, id: &WorkerId) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let stopped_at = format_time(&OffsetDateTime::now_utc())?;
+            conn.execute(
+                "UPDATE workers SET stopped_at = ?2 WHERE worker_id = ?1",
+                params![id, stopped_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_workers(&self) -> anyhow::Result<Vec<WorkerEntry>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT worker_id, manifest, created_at, error FROM workers
+                 WHERE stopped_at IS NULL ORDER BY rowid",
+            )?;
+            let workers = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                })?
+                .map(|r| {
+                    let (id, manifest, created_at, error) = r?;
+                    Ok(WorkerEntry {
+                        worker_id: id.parse()?,
+                        manifest: serde_json::from_str(&manifest)?,
+                        created_at: parse_time(&created_at)?,
+                        error,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(workers)
+        })
+        .await
+    }
+
+    async fn set_worker_error(&self, id: &WorkerId, error: Option<&str>) -> anyhow::Result<()> {
+        let id = id.to_string();
+        let error = error.map(str::to_string);
+        self.with_conn(move |conn| {
+            let n = conn.execute(
+                "UPDATE workers SET error = ?2 WHERE worker_id = ?1",
+                params![id, error],
+            )?;
+            if n == 0 {
+                return Err(anyhow::anyhow!("worker {} is not registered", id));
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Execution, InstanceManifest, Module, Runtime};
+
+    async fn find(register: &SqliteRegister, id: &InstanceId) -> Option<InstanceEntry> {
+        let entries = register.list(true).await.unwrap();
+        entries.into_iter().find(|e| e.instance_id == *id)
+    }
+
+    fn entry() -> InstanceEntry {
+        let module = Module {
+            path: "wasmedge-app.wasm".into(),
+            digest: ModuleDigest::new("0123abcd"),
+        };
+        InstanceEntry::new(WorkerId::generate(), InstanceId::generate(), &module)
+    }
+
+    #[tokio::test]
+    async fn test_register_and_update() {
+        let register = SqliteRegister::open_in_memory().unwrap();
+        let entry = entry();
+
+        register.register(&entry).await.unwrap();
+        assert_eq!(
+            find(&register, &entry.instance_id).await,
+            Some(entry.clone())
+        );
+
+        register
+            .update(&entry.instance_id, InstanceStatus::Running)
+            .await
+            .unwrap();
+        register
+            .update(&entry.instance_id, InstanceStatus::Quit)
+            .await
+            .unwrap();
+
+        let found = find(&register, &entry.instance_id).await.unwrap();
+        assert_eq!(found.status, InstanceStatus::Quit);
+
+        let history = register
+            .history(&entry.instance_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            [
+                InstanceStatus::Starting,
+                InstanceStatus::Running,
+                InstanceStatus::Quit
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_unknown_instance() {
+        let register = SqliteRegister::open_in_memory().unwrap();
+        assert!(register
+            .update(&InstanceId::generate(), InstanceStatus::Running)
+            .await
+            .is_err());
+        assert_eq!(find(&register, &InstanceId::generate()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_across_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("instance-manager.db");
+        let (a, b) = (entry(), entry());
+
+        {
+            let register = SqliteRegister::open(&path).unwrap();
+            register.register(&a).await.unwrap();
+            register.register(&b).await.unwrap();
+        }
+
+        let register = SqliteRegister::open(&path).unwrap();
+        let ids = register
+            .list(true)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.instance_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&a.instance_id) && ids.contains(&b.instance_id));
+    }
+
+    #[tokio::test]
+    async fn test_open_unversioned_register() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("instance-manager.db");
+        let a = entry();
+
+        // the schema as it was before `user_version` was kept
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.execute(
+                "INSERT INTO instances (instance_id, worker_id, module_digest, module_path, status, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    a.instance_id.to_string(),
+                    a.worker_id.to_string(),
+                    a.module_digest.as_str(),
+                    a.module_path.to_string_lossy(),
+                    a.status.as_str(),
+                    format_time(&a.created_at).unwrap(),
+                ],
+            )
+            .unwrap();
+        }
+
+        let register = SqliteRegister::open(&path).unwrap();
+        assert_eq!(register.list(true).await.unwrap(), [a]);
+        assert!(register.list_workers().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_in_creation_order() {
+        let register = SqliteRegister::open_in_memory().unwrap();
+        let (mut a, mut b) = (entry(), entry());
+        // "...:00Z" sorts after "...:00.5Z" as text
+        a.created_at = OffsetDateTime::from_unix_timestamp(1714564800).unwrap();
+        b.created_at = a.created_at + time::Duration::milliseconds(500);
+
+        register.register(&a).await.unwrap();
+        register.register(&b).await.unwrap();
+        assert_eq!(register.list(true).await.unwrap(), [a, b]);
+    }
+
+    #[tokio::test]
+    async fn test_list_without_stopped() {
+        let register = SqliteRegister::open_in_memory().unwrap();
+        let (a, b) = (entry(), entry());
+
+        register.register(&a).await.unwrap();
+        register.register(&b).await.unwrap();
+        register
+            .update(&a.instance_id, InstanceStatus::Quit)
+            .await
+            .unwrap();
+
+        assert_eq!(register.list(false).await.unwrap(), [b]);
+        assert_eq!(register.list(true).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_set_process() {
+        let register = SqliteRegister::open_in_memory().unwrap();
+        let entry = entry();
+        let process = ProcessInfo {
+            pid: Pid(4321),
+            start_time: 987654,
+        };
+
+        register.register(&entry).await.unwrap();
+        register
+            .set_process(&entry.instance_id, process)
+            .await
+            .unwrap();
+
+        let found = find(&register, &entry.instance_id).await.unwrap();
+        assert_eq!(found.process, Some(process));
+    }
+
+    #[tokio::test]
+    async fn test_workers() {
+        let register = SqliteRegister::open_in_memory().unwrap();
+        let manifest = WorkerManifest {
+            instance_manifest: InstanceManifest {
+                module: "wasmedge-app@v1".parse().unwrap(),
+                args: vec!["--enable-all".into()],
+                port: 1234,
+                runtime: Runtime::Wasmedge,
+                execution: Execution::Aot,
+            },
+            detached: true,
+            health_check: Default::default(),
+            restart: Default::default(),
+        };
+        let (a, mut b) = (
+            WorkerEntry::new(WorkerId::generate(), &manifest),
+            WorkerEntry::new(WorkerId::generate(), &manifest),
+        );
+
+        register.register_worker(&a).await.unwrap();
+        register.register_worker(&b).await.unwrap();
+        register.deregister_worker(&a.worker_id).await.unwrap();
+
+        b.manifest.instance_manifest.module = "wasmedge-app@v2".parse().unwrap();
+        register
+            .update_worker(&b.worker_id, &b.manifest)
+            .await
+            .unwrap();
+
+        assert_eq!(register.list_workers().await.unwrap(), [b.clone()]);
+
+        register
+            .set_worker_error(&b.worker_id, Some("module not found"))
+            .await
+            .unwrap();
+        let found = register.list_workers().await.unwrap();
+        assert_eq!(found[0].error.as_deref(), Some("module not found"));
+        register.set_worker_error(&b.worker_id, None).await.unwrap();
+        assert_eq!(register.list_workers().await.unwrap(), [b]);
+    }
+}
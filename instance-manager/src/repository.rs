@@ -1,9 +1,9 @@
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use time::PrimitiveDateTime;
+use time::{format_description::well_known::Rfc3339, PrimitiveDateTime};
 use tokio::sync::mpsc::Sender;
 
-use crate::domain::InstanceMemoryMetrics;
+use crate::domain::{InstanceMemoryMetrics, RestartEvent};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InstanceMemoryMetricsData {
@@ -47,3 +47,61 @@ impl CsvInstanceMemoryRepository {
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestartEventData {
+    pub timestamp: String,
+    pub worker_id: String,
+    pub old_instance_id: String,
+    pub new_instance_id: Option<String>,
+    pub trigger: String,
+    /// "ok" or "failed"
+    pub outcome: String,
+    pub uss_before_kb: Option<u32>,
+    pub uss_after_kb: Option<u32>,
+    pub host_before_kb: Option<u32>,
+    pub host_after_kb: Option<u32>,
+    pub downtime_ms: u128,
+    pub spawn_to_ready_ms: Option<u128>,
+    pub error: Option<String>,
+}
+
+impl From<RestartEvent> for RestartEventData {
+    fn from(e: RestartEvent) -> Self {
+        RestartEventData {
+            timestamp: e
+                .timestamp
+                .format(&Rfc3339)
+                .unwrap_or_else(|_| e.timestamp.to_string()),
+            worker_id: e.worker_id.to_string(),
+            old_instance_id: e.old_instance_id.to_string(),
+            new_instance_id: e.new_instance_id.map(|id| id.to_string()),
+            trigger: e.trigger.to_string(),
+            outcome: e.outcome().to_string(),
+            uss_before_kb: e.before.instance,
+            uss_after_kb: e.after.instance,
+            host_before_kb: e.before.host,
+            host_after_kb: e.after.host,
+            downtime_ms: e.downtime.as_millis(),
+            spawn_to_ready_ms: e.spawn_to_ready.map(|d| d.as_millis()),
+            error: e.error,
+        }
+    }
+}
+
+/// Sends restart events to whichever export driver owns the receiver.
+#[derive(Debug, Clone)]
+pub struct RestartEventRepository {
+    sender: Sender<RestartEventData>,
+}
+
+impl RestartEventRepository {
+    pub fn new(sender: Sender<RestartEventData>) -> Self {
+        Self { sender }
+    }
+
+    pub async fn store(&self, event: impl Into<RestartEventData>) -> anyhow::Result<()> {
+        self.sender.send(event.into()).await?;
+        Ok(())
+    }
+}
@@ -1,28 +1,400 @@
-use std::process::Stdio;
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
 
-use tokio::process::Command;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use tokio::process::Child;
 
-use crate::domain::{Instance, InstanceId, InstanceManifest, Worker, WorkerId, WorkerManifest};
+use crate::{
+    aot::AotCache,
+    domain::{
+        Execution, Instance, InstanceFactory, InstanceId, InstanceManifest, InstanceStatus, Module,
+        ModuleRef, Pid, Process, ProcessInfo, RestartTrigger, Worker, WorkerControl, WorkerId,
+        WorkerManifest,
+    },
+    register::{InstanceEntry, InstanceRegister, WorkerEntry},
+    repository::RestartEventRepository,
+    store::ModuleStore,
+};
 
-pub async fn instance_create_service(man: &InstanceManifest) -> Instance {
+/// Dependencies shared by the services.
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub register: Arc<dyn InstanceRegister>,
+    pub store: ModuleStore,
+    pub aot: AotCache,
+    pub events: RestartEventRepository,
+    /// stdout and stderr of every instance are written here
+    pub log_dir: PathBuf,
+}
+
+#[async_trait]
+impl InstanceFactory for Context {
+    async fn create(
+        &self,
+        man: &InstanceManifest,
+        worker_id: WorkerId,
+        detached: bool,
+    ) -> anyhow::Result<Instance> {
+        instance_create_service(man, worker_id, detached, self).await
+    }
+}
+
+/// `executable` is the module itself, or its AOT artifact when the manifest asks for one.
+fn spawn_process(
+    man: &InstanceManifest,
+    executable: &Path,
+    id: InstanceId,
+    detached: bool,
+    ctx: &Context,
+) -> io::Result<Child> {
+    std::fs::create_dir_all(&ctx.log_dir)?;
+    let stdout = File::create(ctx.log_dir.join(format!("{}.stdout", id)))?;
+    let stderr = File::create(ctx.log_dir.join(format!("{}.stderr", id)))?;
+
+    let mut command = man
+        .runtime
+        .run_command(man, executable, man.execution == Execution::Aot);
+    command
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr)
+        .kill_on_drop(!detached);
+
+    if detached {
+        // a session of its own keeps the instance out of reach of the terminal's
+        // SIGINT and of the manager's exit
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+    }
+
+    command.spawn()
+}
+
+pub async fn instance_create_service(
+    man: &InstanceManifest,
+    worker_id: WorkerId,
+    detached: bool,
+    ctx: &Context,
+) -> anyhow::Result<Instance> {
     let id = InstanceId::generate();
+    let module = ctx.store.resolve(&man.module).await?;
+    let executable = match man.execution {
+        Execution::Interpreted => module.path.clone(),
+        Execution::Aot => ctx.aot.compile(man.runtime, &module).await?,
+    };
+    ctx.register
+        .register(&InstanceEntry::new(worker_id, id, &module))
+        .await?;
 
-    let child = Command::new("wasmedge")
-        .args(&man.args)
-        .env("PORT", man.port.to_string().as_str())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .kill_on_drop(true)
-        .spawn()
-        .unwrap();
+    let child = match spawn_process(man, &executable, id, detached, ctx) {
+        Ok(child) => child,
+        Err(e) => {
+            ctx.register.update(&id, InstanceStatus::Quit).await?;
+            return Err(e.into());
+        }
+    };
+    if let Some(pid) = child.id() {
+        let process = ProcessInfo::of(Pid(pid)).await?;
+        ctx.register.set_process(&id, process).await?;
+    }
+    ctx.register.update(&id, InstanceStatus::Running).await?;
 
-    Instance::new(id, child)
+    Ok(Instance::new(
+        id,
+        module,
+        Process::Child(child),
+        detached,
+        ctx.register.clone(),
+    ))
 }
 
-pub async fn worker_create_service(man: &WorkerManifest) -> Worker {
+pub async fn worker_create_service(
+    man: &WorkerManifest,
+    ctx: &Arc<Context>,
+) -> anyhow::Result<Worker> {
     let id = WorkerId::generate();
-    let instance = instance_create_service(&man.instance_manifest).await;
-    let instance_handler = instance.spawn();
+    // a path is recorded by digest, so that the worker keeps its module when the file changes
+    let mut man = man.clone();
+    if let ModuleRef::Path(_) = man.instance_manifest.module {
+        let module = ctx.store.resolve(&man.instance_manifest.module).await?;
+        man.instance_manifest.module = ModuleRef::Digest(module.digest);
+    }
+    ctx.register
+        .register_worker(&WorkerEntry::new(id, &man))
+        .await?;
+
+    let instance =
+        match instance_create_service(&man.instance_manifest, id, man.detached, ctx).await {
+            Ok(instance) => instance,
+            Err(e) => {
+                ctx.register.deregister_worker(&id).await?;
+                return Err(e);
+            }
+        };
+
+    Ok(Worker::new(
+        id,
+        man,
+        instance,
+        ctx.clone(),
+        ctx.register.clone(),
+        ctx.events.clone(),
+    ))
+}
+
+/// Restores the workers left behind by a previous run of the manager.
+///
+/// Detached workers are reattached to their instance if it is still alive and
+/// respawned from their manifest otherwise. Instances of attached workers
+/// should have died with the manager, so any survivor is terminated and the
+/// worker is dropped. A worker which cannot be recovered keeps its record,
+/// with the error, and is tried again on the next start.
+pub async fn worker_recover_service(ctx: &Arc<Context>) -> anyhow::Result<Vec<Worker>> {
+    let instances = ctx.register.list(false).await?;
+    let mut workers = Vec::new();
+
+    for worker in ctx.register.list_workers().await? {
+        let id = worker.worker_id;
+        let failed_before = worker.error.is_some();
+        if let Some(error) = &worker.error {
+            tracing::info!("worker {} could not be recovered last time: {}", id, error);
+        }
+
+        let error = match recover_worker(worker, &instances, ctx).await {
+            Ok(recovered) => {
+                workers.extend(recovered);
+                None
+            }
+            Err(e) => {
+                tracing::error!("failed to recover worker {}: {:#}", id, e);
+                Some(format!("{:#}", e))
+            }
+        };
+        if error.is_some() || failed_before {
+            if let Err(e) = ctx.register.set_worker_error(&id, error.as_deref()).await {
+                tracing::warn!("failed to record recovery of worker {}: {:#}", id, e);
+            }
+        }
+    }
+
+    Ok(workers)
+}
+
+/// Recovers one worker of `worker_recover_service`, `None` when it is dropped.
+async fn recover_worker(
+    worker: WorkerEntry,
+    instances: &[InstanceEntry],
+    ctx: &Arc<Context>,
+) -> anyhow::Result<Option<Worker>> {
+    let detached = worker.manifest.detached;
+    let mut live = None;
+
+    // newest first, so that the latest instance is the one kept
+    for entry in instances
+        .iter()
+        .rev()
+        .filter(|e| e.worker_id == worker.worker_id)
+    {
+        let last = last_status(ctx, &entry.instance_id).await?;
+        match entry.process {
+            Some(process) if process.is_alive().await => {
+                if detached && live.is_none() {
+                    live = Some((entry, process, last));
+                    continue;
+                }
+                tracing::info!(
+                    "terminating stale instance {} (pid {}, {})",
+                    entry.instance_id,
+                    process.pid,
+                    last
+                );
+                process.pid.terminate()?;
+            }
+            _ => tracing::info!("instance {} is gone ({})", entry.instance_id, last),
+        }
+        ctx.register
+            .update(&entry.instance_id, InstanceStatus::Quit)
+            .await?;
+    }
+
+    if !detached {
+        ctx.register.deregister_worker(&worker.worker_id).await?;
+        return Ok(None);
+    }
+
+    let instance = match live {
+        Some((entry, process, last)) => {
+            tracing::info!(
+                "reattached worker {} to instance {} (pid {}, {})",
+                worker.worker_id,
+                entry.instance_id,
+                process.pid,
+                last
+            );
+            let module = Module {
+                path: entry.module_path.clone(),
+                digest: entry.module_digest.clone(),
+            };
+            Instance::new(
+                entry.instance_id,
+                module,
+                Process::Attached(process),
+                true,
+                ctx.register.clone(),
+            )
+        }
+        None => {
+            tracing::info!("respawning worker {}", worker.worker_id);
+            instance_create_service(
+                &worker.manifest.instance_manifest,
+                worker.worker_id,
+                true,
+                ctx,
+            )
+            .await?
+        }
+    };
+
+    Ok(Some(Worker::new(
+        worker.worker_id,
+        worker.manifest,
+        instance,
+        ctx.clone(),
+        ctx.register.clone(),
+        ctx.events.clone(),
+    )))
+}
+
+/// The latest status change of the instance `id`, for the recovery log.
+async fn last_status(ctx: &Context, id: &InstanceId) -> anyhow::Result<String> {
+    Ok(match ctx.register.history(id).await?.pop() {
+        Some(change) => change.to_string(),
+        None => "no status recorded".to_string(),
+    })
+}
+
+/// Moves every worker to `module`, one worker at a time.
+///
+/// A worker whose new instance fails its health check is restored by the
+/// worker itself; the rollout then stops and moves the workers which were
+/// already updated back to the module they ran before. Every one of them is
+/// tried, and those which could not be moved back are named in the error.
+pub async fn pool_rollout_service(
+    workers: &[WorkerControl],
+    module: &ModuleRef,
+    ctx: &Context,
+) -> anyhow::Result<()> {
+    // resolve once, so that every worker gets the same module even if the tag moves meanwhile
+    let target = ModuleRef::Digest(ctx.store.resolve(module).await?.digest);
+    let mut updated: Vec<(&WorkerControl, WorkerManifest)> = Vec::new();
+
+    for worker in workers {
+        let previous = worker.manifest().await?;
+        let mut manifest = previous.clone();
+        manifest.instance_manifest.module = target.clone();
+
+        if let Err(e) = worker.update(manifest).await {
+            tracing::warn!("rollout of {} stopped at worker {}", module, worker.id);
+            let mut failed = Vec::new();
+            for (worker, previous) in updated.into_iter().rev() {
+                if let Err(e) = worker.update(previous).await {
+                    tracing::error!("failed to roll back worker {}: {:#}", worker.id, e);
+                    failed.push(format!("worker {}: {:#}", worker.id, e));
+                }
+            }
+            let e = e.context(format!("rollout of {} failed", module));
+            if failed.is_empty() {
+                return Err(e);
+            }
+            return Err(anyhow::anyhow!(
+                "{:#}; failed to roll back {}",
+                e,
+                failed.join("; ")
+            ));
+        }
+        updated.push((worker, previous));
+    }
+
+    tracing::info!("rolled out {} to {} workers", module, workers.len());
+    Ok(())
+}
+
+/// Restarts the worker `id`, or every worker one at a time when `id` is `None`.
+pub async fn pool_restart_service(
+    workers: &[WorkerControl],
+    id: Option<WorkerId>,
+) -> anyhow::Result<()> {
+    let mut found = false;
+    for worker in workers.iter().filter(|w| id.is_none_or(|id| w.id == id)) {
+        found = true;
+        worker
+            .restart(RestartTrigger::Manual)
+            .await
+            .with_context(|| format!("failed to restart worker {}", worker.id))?;
+    }
+    match id {
+        Some(id) if !found => Err(anyhow::anyhow!("unknown worker: {}", id)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{Execution, Runtime},
+        register::SqliteRegister,
+    };
+
+    #[tokio::test]
+    async fn test_recover_past_failed_workers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, _receiver) = tokio::sync::mpsc::channel(1);
+        let ctx = Arc::new(Context {
+            register: Arc::new(SqliteRegister::open_in_memory().unwrap()),
+            store: ModuleStore::new(dir.path().join("modules")),
+            aot: AotCache::new(dir.path().join("aot")),
+            events: RestartEventRepository::new(sender),
+            log_dir: dir.path().join("logs"),
+        });
+        let manifest = |module: &str, detached| WorkerManifest {
+            instance_manifest: InstanceManifest {
+                module: module.parse().unwrap(),
+                args: vec![],
+                port: 1234,
+                runtime: Runtime::Wasmedge,
+                execution: Execution::Interpreted,
+            },
+            detached,
+            health_check: Default::default(),
+            restart: Default::default(),
+        };
+        // neither module is in the store
+        let a = WorkerEntry::new(WorkerId::generate(), &manifest("app@v1", true));
+        let b = WorkerEntry::new(WorkerId::generate(), &manifest("app@v2", true));
+        let c = WorkerEntry::new(WorkerId::generate(), &manifest("app@v1", false));
+        for worker in [&a, &b, &c] {
+            ctx.register.register_worker(worker).await.unwrap();
+        }
+
+        assert!(worker_recover_service(&ctx).await.unwrap().is_empty());
 
-    Worker::new(id, instance_handler)
+        let left = ctx.register.list_workers().await.unwrap();
+        let ids = left.iter().map(|w| w.worker_id).collect::<Vec<_>>();
+        assert_eq!(ids, [a.worker_id, b.worker_id]);
+        assert!(left.iter().all(|w| w.error.is_some()));
+    }
 }
@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::domain::{Module, ModuleDigest, ModuleRef};
+
+/// Content addressed store of wasm modules.
+///
+/// ```text
+/// <root>/blobs/sha256/<digest>.wasm
+/// <root>/tags/<name>/<tag>            (holds the digest)
+/// ```
+///
+/// Blobs are never modified once written, so the path of a blob always refers
+/// to the same module.
+#[derive(Debug, Clone)]
+pub struct ModuleStore {
+    root: PathBuf,
+}
+
+impl ModuleStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn blob_path(&self, digest: &ModuleDigest) -> PathBuf {
+        self.root
+            .join("blobs")
+            .join("sha256")
+            .join(format!("{}.wasm", digest))
+    }
+
+    fn tag_path(&self, name: &str, tag: &str) -> PathBuf {
+        self.root.join("tags").join(name).join(tag)
+    }
+
+    fn validate_name(s: &str) -> anyhow::Result<()> {
+        let valid = !s.is_empty()
+            && s != "."
+            && s != ".."
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("invalid module name or tag: {:?}", s))
+        }
+    }
+
+    /// Writes `data` to `path` through a temporary file, so readers never see a partial file.
+    async fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow::anyhow!("{} has no parent", path.display()))?;
+        fs::create_dir_all(dir).await?;
+        let tmp = dir.join(format!(".{}.tmp", ulid::Ulid::new()));
+        fs::write(&tmp, data).await?;
+        fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    /// Copies the module at `path` into the store.
+    pub async fn import(&self, path: &Path) -> anyhow::Result<Module> {
+        let data = fs::read(path).await?;
+        let digest = Module::digest_of(&data);
+        let blob = self.blob_path(&digest);
+        if !blob.exists() {
+            Self::write_atomic(&blob, &data).await?;
+        }
+        Ok(Module { path: blob, digest })
+    }
+
+    /// Points `name@tag` at `digest`, replacing the previous target of the tag.
+    pub async fn tag(&self, name: &str, tag: &str, digest: &ModuleDigest) -> anyhow::Result<()> {
+        Self::validate_name(name)?;
+        Self::validate_name(tag)?;
+        if !self.blob_path(digest).exists() {
+            return Err(anyhow::anyhow!("module {} is not in the store", digest));
+        }
+        Self::write_atomic(&self.tag_path(name, tag), digest.as_str().as_bytes()).await
+    }
+
+    /// Tags of `name` and the digests they point at, sorted by tag.
+    pub async fn tags(&self, name: &str) -> anyhow::Result<Vec<(String, ModuleDigest)>> {
+        Self::validate_name(name)?;
+        let dir = self.root.join("tags").join(name);
+        let mut tags = Vec::new();
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(tags),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let tag = entry.file_name().to_string_lossy().into_owned();
+            if tag.starts_with('.') {
+                continue;
+            }
+            let digest = fs::read_to_string(entry.path()).await?.trim().parse()?;
+            tags.push((tag, digest));
+        }
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// The module `r` refers to, in the store. A file path is imported first,
+    /// so that the module can be referred to by digest from then on, even once
+    /// the file has been rebuilt.
+    pub async fn resolve(&self, r: &ModuleRef) -> anyhow::Result<Module> {
+        let digest = match r {
+            ModuleRef::Path(path) => return self.import(path).await,
+            ModuleRef::Digest(digest) => digest.clone(),
+            ModuleRef::Tagged { name, tag } => {
+                Self::validate_name(name)?;
+                Self::validate_name(tag)?;
+                let path = self.tag_path(name, tag);
+                fs::read_to_string(&path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("unknown module {}: {}", r, e))?
+                    .trim()
+                    .parse()?
+            }
+        };
+
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            return Err(anyhow::anyhow!("module {} is not in the store", digest));
+        }
+        Ok(Module { path, digest })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_import_and_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ModuleStore::new(dir.path().join("modules"));
+        let src = dir.path().join("app.wasm");
+
+        fs::write(&src, b"v1").await.unwrap();
+        let v1 = store.import(&src).await.unwrap();
+        store.tag("app", "v1", &v1.digest).await.unwrap();
+        store.tag("app", "latest", &v1.digest).await.unwrap();
+
+        // overwriting the source does not touch the imported blob
+        fs::write(&src, b"v2").await.unwrap();
+        let v2 = store.import(&src).await.unwrap();
+        assert_ne!(v1.digest, v2.digest);
+        store.tag("app", "latest", &v2.digest).await.unwrap();
+
+        let resolved = store.resolve(&"app@v1".parse().unwrap()).await.unwrap();
+        assert_eq!(resolved, v1);
+        assert_eq!(fs::read(&resolved.path).await.unwrap(), b"v1");
+
+        let resolved = store
+            .resolve(&ModuleRef::Digest(v2.digest.clone()))
+            .await
+            .unwrap();
+        assert_eq!(resolved, v2);
+
+        assert_eq!(
+            store.tags("app").await.unwrap(),
+            [
+                ("latest".to_string(), v2.digest),
+                ("v1".to_string(), v1.digest)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ModuleStore::new(dir.path().join("modules"));
+        let src = dir.path().join("app.wasm");
+
+        fs::write(&src, b"v1").await.unwrap();
+        let v1 = store.resolve(&ModuleRef::Path(src.clone())).await.unwrap();
+        assert_ne!(v1.path, src);
+
+        // a rebuilt file does not change what the digest refers to
+        fs::write(&src, b"v2").await.unwrap();
+        let pinned = store
+            .resolve(&ModuleRef::Digest(v1.digest.clone()))
+            .await
+            .unwrap();
+        assert_eq!(pinned, v1);
+        assert_eq!(fs::read(&pinned.path).await.unwrap(), b"v1");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_unknown() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ModuleStore::new(dir.path());
+
+        assert!(store.resolve(&"app@v1".parse().unwrap()).await.is_err());
+        assert!(store
+            .resolve(&ModuleRef::Digest(ModuleDigest::new("00".repeat(32))))
+            .await
+            .is_err());
+        assert!(store
+            .tag("app", "v1", &ModuleDigest::new("00".repeat(32)))
+            .await
+            .is_err());
+        assert!(store
+            .tag("../app", "v1", &ModuleDigest::new("00"))
+            .await
+            .is_err());
+        assert!(store.tags("app").await.unwrap().is_empty());
+    }
+}
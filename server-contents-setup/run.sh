@@ -11,8 +11,5 @@ for target in $(find . -name index.html); do
     sed -i "s|<img src=\".|<img src=\"|g" $target
 done
 
-# ### make root index.html
-# cd static
-# for target in $(exa -D -s name); do
-#     echo "<a href=${target}>${target}</a>" >> index.html
-# done
+### root index.html
+# wasmedge-app lists directories without one when run with LISTINGS=true
@@ -0,0 +1,149 @@
+use std::{
+    fmt,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::request::Request;
+
+/// A request being answered, logged as a line of JSON once its response has
+/// been written:
+///
+/// ```text
+/// {"time":"2024-05-01T12:00:00.123Z","method":"GET","path":"/","status":200,"bytes":512,"duration_ms":0.412}
+/// ```
+///
+/// `time` is when the request had been read, and `duration_ms` runs from
+/// then until the response has been written. Requests which could not be
+/// read have a `null` method and path.
+#[derive(Debug)]
+pub struct Access {
+    time: SystemTime,
+    started: Instant,
+    method: Option<String>,
+    path: Option<String>,
+    pub status: u16,
+    /// bytes of the response written so far, head included
+    pub sent: u64,
+}
+
+impl Access {
+    pub fn new(req: Option<&Request>) -> Self {
+        Self {
+            time: SystemTime::now(),
+            started: Instant::now(),
+            method: req.map(|req| req.method.clone()),
+            path: req.map(|req| req.target.clone()),
+            status: 0,
+            sent: 0,
+        }
+    }
+
+    /// Writes the line to stdout.
+    pub fn log(&self) {
+        println!("{}", self);
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"time\":\"{}\",\"method\":", rfc3339(self.time))?;
+        write_string(f, self.method.as_deref())?;
+        f.write_str(",\"path\":")?;
+        write_string(f, self.path.as_deref())?;
+        write!(
+            f,
+            ",\"status\":{},\"bytes\":{},\"duration_ms\":{:.3}}}",
+            self.status,
+            self.sent,
+            self.started.elapsed().as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// `s` as a JSON string, `null` for `None`.
+fn write_string(f: &mut fmt::Formatter<'_>, s: Option<&str>) -> fmt::Result {
+    let s = match s {
+        Some(s) => s,
+        None => return f.write_str("null"),
+    };
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// `time` as an RFC 3339 UTC timestamp with milliseconds.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    // from the days since the epoch to the date, after Howard Hinnant's
+    // `civil_from_days`, with years starting in March
+    let days = secs / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = match month {
+        0..=9 => (era * 400 + year_of_era, month + 3),
+        _ => (era * 400 + year_of_era + 1, month - 9),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_rfc3339() {
+        let at = |secs, millis| {
+            rfc3339(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+        };
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(784111777, 5), "1994-11-06T08:49:37.005Z");
+        assert_eq!(at(951825600, 0), "2000-02-29T12:00:00.000Z");
+        assert_eq!(at(1735689599, 999), "2024-12-31T23:59:59.999Z");
+    }
+
+    #[test]
+    fn test_display() {
+        let req = Request {
+            method: "GET".to_string(),
+            target: "/a\"b\\c\u{1}".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let mut access = Access::new(Some(&req));
+        access.status = 200;
+        access.sent = 42;
+        let line = access.to_string();
+        assert!(line.starts_with("{\"time\":\""));
+        assert!(line.contains(
+            "\"method\":\"GET\",\"path\":\"/a\\\"b\\\\c\\u0001\",\"status\":200,\"bytes\":42,\"duration_ms\":"
+        ));
+
+        let line = Access::new(None).to_string();
+        assert!(line.contains("\"method\":null,\"path\":null,\"status\":0,"));
+    }
+}
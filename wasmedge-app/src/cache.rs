@@ -0,0 +1,136 @@
+use std::{
+    fs::Metadata,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::request::Request;
+
+/// What clients revalidate their cached copy of a file with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    /// strong, from the size and modification time of the file
+    pub etag: String,
+    /// whole seconds, as HTTP dates have no more precision
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub fn new(metadata: &Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        Self {
+            etag: format!(
+                "\"{:x}-{:x}\"",
+                modified.unwrap_or_default().as_nanos(),
+                metadata.len()
+            ),
+            last_modified: modified.map(|d| UNIX_EPOCH + Duration::from_secs(d.as_secs())),
+        }
+    }
+
+    pub fn last_modified_header(&self) -> Option<String> {
+        self.last_modified.map(httpdate::fmt_http_date)
+    }
+
+    /// Whether the client's copy is current, so a 304 can be sent. As
+    /// RFC 7232 says, `If-Modified-Since` only counts without
+    /// `If-None-Match`, and invalid dates are ignored.
+    pub fn not_modified(&self, req: &Request) -> bool {
+        if let Some(tags) = req.header("if-none-match") {
+            return tags
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag);
+        }
+        match (req.header("if-modified-since"), self.last_modified) {
+            (Some(since), Some(modified)) => {
+                httpdate::parse_http_date(since).is_ok_and(|since| modified <= since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a `Range` header applies: always without `If-Range`, and
+    /// only for the current strong ETag or exact date with it.
+    pub fn range_applies(&self, req: &Request) -> bool {
+        let condition = match req.header("if-range") {
+            Some(condition) => condition,
+            None => return true,
+        };
+        if condition.starts_with('"') || condition.starts_with("W/") {
+            return condition == self.etag;
+        }
+        match (httpdate::parse_http_date(condition), self.last_modified) {
+            (Ok(date), Some(modified)) => date == modified,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            target: "/".to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: headers
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+            body: Vec::new(),
+        }
+    }
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"17a-2a\"".to_string(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784111777)),
+        }
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let validators = validators();
+        let not_modified = |headers: &[(&str, &str)]| validators.not_modified(&request(headers));
+
+        assert_eq!(
+            validators.last_modified_header().unwrap(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert!(!not_modified(&[]));
+        assert!(not_modified(&[("If-None-Match", "\"17a-2a\"")]));
+        assert!(not_modified(&[("If-None-Match", "\"x\", W/\"17a-2a\"")]));
+        assert!(not_modified(&[("If-None-Match", "*")]));
+        assert!(!not_modified(&[("If-None-Match", "\"17a-2b\"")]));
+
+        let since = |date| not_modified(&[("If-Modified-Since", date)]);
+        assert!(since("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(since("Mon, 07 Nov 1994 08:49:37 GMT"));
+        assert!(!since("Sun, 06 Nov 1994 08:49:36 GMT"));
+        assert!(!since("yesterday"));
+
+        // the ETag wins over the date
+        assert!(!not_modified(&[
+            ("If-None-Match", "\"17a-2b\""),
+            ("If-Modified-Since", "Mon, 07 Nov 1994 08:49:37 GMT"),
+        ]));
+    }
+
+    #[test]
+    fn test_range_applies() {
+        let validators = validators();
+        let applies = |condition| validators.range_applies(&request(&[("If-Range", condition)]));
+
+        assert!(validators.range_applies(&request(&[])));
+        assert!(applies("\"17a-2a\""));
+        assert!(!applies("W/\"17a-2a\""));
+        assert!(!applies("\"17a-2b\""));
+        assert!(applies("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!applies("Mon, 07 Nov 1994 08:49:37 GMT"));
+    }
+}
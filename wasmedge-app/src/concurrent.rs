@@ -0,0 +1,321 @@
+//! Serves many connections at once from a single thread: sockets are
+//! nonblocking, and each connection goes as far as it can whenever `poll`
+//! says its socket is ready.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{ErrorKind, Write},
+    time::SystemTime,
+};
+
+use wasmedge_wasi_socket::{
+    poll::{self, EventType, Subscription},
+    Shutdown, TcpListener, TcpStream,
+};
+
+use crate::{
+    access::Access,
+    files::CHUNK_SIZE,
+    request::RequestReader,
+    response::{encode_head, Body},
+    stats::Stats,
+    Config, State,
+};
+
+/// `poll` user data of the listener.
+const LISTENER: u64 = u64::MAX;
+/// `poll` user data of the earliest idle timeout.
+const TIMEOUT: u64 = u64::MAX - 1;
+
+/// A response on its way to the client.
+struct Outgoing {
+    /// bytes to write in order, from `pos` in the first
+    pending: VecDeque<Vec<u8>>,
+    pos: usize,
+    /// the rest of a file or cached body, taken a chunk at a time once
+    /// `pending` has been written
+    rest: Option<Body>,
+    keep_alive: bool,
+    access: Access,
+}
+
+impl Outgoing {
+    /// The head goes out with the start of the body, see `write_response`.
+    fn new(
+        head: Vec<u8>,
+        body: Option<Body>,
+        keep_alive: bool,
+        access: Access,
+    ) -> std::io::Result<Self> {
+        let mut first = head;
+        let mut pending = VecDeque::new();
+        let mut rest = None;
+        match body {
+            Some(Body::Bytes(bytes)) if bytes.len() <= CHUNK_SIZE => first.extend(bytes),
+            Some(Body::Bytes(bytes)) => pending.push_back(bytes),
+            Some(mut body) => {
+                let start = first.len();
+                first.resize(start + CHUNK_SIZE, 0);
+                let n = body.read_chunk(&mut first[start..])?;
+                first.truncate(start + n);
+                rest = Some(body);
+            }
+            None => {}
+        }
+        pending.push_front(first);
+        Ok(Self {
+            pending,
+            pos: 0,
+            rest,
+            keep_alive,
+            access,
+        })
+    }
+}
+
+/// Where a connection stands after going as far as it could.
+enum Progress {
+    /// the socket has to be ready again
+    Blocked,
+    /// a request has been read or a response written
+    Ready,
+    Closed,
+}
+
+struct Connection {
+    stream: TcpStream,
+    reader: RequestReader,
+    /// the response being written, the request being read while `None`
+    out: Option<Outgoing>,
+    served: usize,
+    /// when the connection is closed unless the client makes progress
+    deadline: SystemTime,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, config: &Config) -> Self {
+        Self {
+            stream,
+            reader: RequestReader::new(config.limits),
+            out: None,
+            served: 0,
+            deadline: SystemTime::now() + config.idle_timeout,
+            closed: false,
+        }
+    }
+
+    fn subscription(&self, id: u64) -> Subscription {
+        let writing = self.out.is_some();
+        Subscription::io(id, &self.stream, !writing, writing, None)
+    }
+
+    /// Reads and answers requests until the socket would block.
+    fn advance(&mut self, config: &Config, state: &mut State) {
+        self.deadline = SystemTime::now() + config.idle_timeout;
+        loop {
+            let progress = if self.out.is_some() {
+                self.write(config, &mut state.stats)
+            } else {
+                self.read(config, state)
+            };
+            match progress {
+                Progress::Blocked => return,
+                Progress::Ready => {}
+                Progress::Closed => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, config: &Config, state: &mut State) -> Progress {
+        loop {
+            let (response, head, keep_alive, mut access) = match self.reader.parse() {
+                Ok(Some(req)) => {
+                    self.served += 1;
+                    let access = Access::new(Some(&req));
+                    let (response, keep_alive) = crate::respond(&req, config, state, self.served);
+                    (response, req.method == "HEAD", keep_alive, access)
+                }
+                Ok(None) => match self.reader.fill(&mut &self.stream) {
+                    // the client is gone, even mid-request
+                    Ok(0) => return Progress::Closed,
+                    Ok(_) => continue,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Progress::Blocked,
+                    Err(_) => return Progress::Closed,
+                },
+                Err(e) => match crate::error_response(&e, &mut state.stats) {
+                    Some(response) => (response, false, false, Access::new(None)),
+                    None => return Progress::Closed,
+                },
+            };
+            access.status = response.status_code().as_u16();
+            let out = encode_head(response, head)
+                .and_then(|(head, body)| Outgoing::new(head, body, keep_alive, access));
+            return match out {
+                Ok(out) => {
+                    self.out = Some(out);
+                    Progress::Ready
+                }
+                Err(_) => Progress::Closed,
+            };
+        }
+    }
+
+    fn write(&mut self, config: &Config, stats: &mut Stats) -> Progress {
+        let out = match &mut self.out {
+            Some(out) => out,
+            None => return Progress::Ready,
+        };
+        loop {
+            if let Some(front) = out.pending.front() {
+                if out.pos == front.len() {
+                    out.pending.pop_front();
+                    out.pos = 0;
+                    continue;
+                }
+                match (&self.stream).write(&front[out.pos..]) {
+                    Ok(0) => return Progress::Closed,
+                    Ok(n) => {
+                        out.pos += n;
+                        out.access.sent += n as u64;
+                        stats.bytes_sent += n as u64;
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Progress::Blocked,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => return Progress::Closed,
+                }
+                continue;
+            }
+
+            if let Some(body) = &mut out.rest {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                match body.read_chunk(&mut chunk) {
+                    Ok(0) => out.rest = None,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        out.pending.push_back(chunk);
+                    }
+                    Err(_) => return Progress::Closed,
+                }
+                continue;
+            }
+
+            if config.access_log {
+                out.access.log();
+            }
+            let keep_alive = out.keep_alive;
+            self.out = None;
+            return if keep_alive {
+                Progress::Ready
+            } else {
+                Progress::Closed
+            };
+        }
+    }
+}
+
+/// Accepts and serves connections until `poll` fails.
+pub fn serve(listener: &TcpListener, config: &Config, state: &mut State) -> std::io::Result<()> {
+    let mut connections = BTreeMap::<u64, Connection>::new();
+    let mut next_id = 0u64;
+    loop {
+        let mut subscriptions = Vec::with_capacity(connections.len() + 2);
+        if connections.len() < config.max_connections {
+            subscriptions.push(Subscription::io(LISTENER, listener, true, false, None));
+        }
+        for (id, connection) in &connections {
+            subscriptions.push(connection.subscription(*id));
+        }
+        if let Some(deadline) = connections.values().map(|c| c.deadline).min() {
+            subscriptions.push(Subscription::timeout(TIMEOUT, deadline));
+        }
+
+        for event in poll::poll(&subscriptions)? {
+            match (event.userdata, event.event_type) {
+                (LISTENER, _) => {
+                    while connections.len() < config.max_connections {
+                        match listener.accept(true) {
+                            Ok((stream, _)) => {
+                                connections.insert(next_id, Connection::new(stream, config));
+                                next_id += 1;
+                            }
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                            Err(e) => {
+                                eprintln!("accept failed: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                (TIMEOUT, _) => {}
+                (id, EventType::Read | EventType::Write) => {
+                    if let Some(connection) = connections.get_mut(&id) {
+                        connection.advance(config, state);
+                    }
+                }
+                (id, EventType::Error(_)) => {
+                    if let Some(connection) = connections.get_mut(&id) {
+                        connection.closed = true;
+                    }
+                }
+                (_, EventType::Timeout) => {}
+            }
+        }
+
+        let now = SystemTime::now();
+        connections.retain(|_, connection| {
+            let open = !connection.closed && connection.deadline > now;
+            if !open {
+                let _ = connection.stream.shutdown(Shutdown::Both);
+            }
+            open
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::files;
+
+    #[test]
+    fn test_outgoing() {
+        let out = Outgoing::new(
+            b"head".to_vec(),
+            Some(Body::Bytes(b"body".to_vec())),
+            true,
+            Access::new(None),
+        )
+        .unwrap();
+        assert_eq!(out.pending, [b"headbody".to_vec()]);
+        assert!(out.rest.is_none());
+
+        let big = vec![1; CHUNK_SIZE + 1];
+        let out = Outgoing::new(
+            b"head".to_vec(),
+            Some(Body::Bytes(big.clone())),
+            true,
+            Access::new(None),
+        )
+        .unwrap();
+        assert_eq!(out.pending, [b"head".to_vec(), big]);
+
+        let file = files::find(Path::new("src"), "/main.rs").unwrap();
+        let len = file.len() as usize;
+        let out = Outgoing::new(
+            b"head".to_vec(),
+            Some(Body::File(file)),
+            false,
+            Access::new(None),
+        )
+        .unwrap();
+        let main = std::fs::read("src/main.rs").unwrap();
+        assert_eq!(out.pending[0][..4], *b"head");
+        assert_eq!(out.pending[0][4..], main[..len.min(CHUNK_SIZE)]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Which entry makes room for a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// the least recently used
+    Lru,
+    /// the least frequently used, the least recently used of them on ties
+    Lfu,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(Policy::Lru),
+            "lfu" => Ok(Policy::Lfu),
+            _ => Err(format!("unknown cache policy {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// of the file when it was read, which must still match
+    etag: String,
+    bytes: Rc<[u8]>,
+    last_used: u64,
+    uses: u64,
+}
+
+/// File contents kept in memory, within a budget of bytes. Entries are
+/// found by path and dropped once the file changes.
+///
+/// Evictions scan all entries, which is fine for the few thousand files of
+/// a content tree.
+#[derive(Debug)]
+pub struct ContentCache {
+    budget: usize,
+    policy: Policy,
+    entries: HashMap<PathBuf, Entry>,
+    /// bytes of content held
+    pub used: usize,
+    /// counts lookups, to order entries by last use
+    clock: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl ContentCache {
+    /// A cache of at most `budget` bytes, disabled with 0.
+    pub fn new(budget: usize, policy: Policy) -> Self {
+        Self {
+            budget,
+            policy,
+            entries: HashMap::new(),
+            used: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Whether a file of `len` bytes is cached at all. Larger files bypass
+    /// the cache and are not counted as misses.
+    pub fn admits(&self, len: u64) -> bool {
+        len <= self.budget as u64 && self.budget > 0
+    }
+
+    /// The content of `path`, if cached for the current `etag`.
+    pub fn get(&mut self, path: &Path, etag: &str) -> Option<Rc<[u8]>> {
+        self.clock += 1;
+        match self.entries.get_mut(path) {
+            Some(entry) if entry.etag == etag => {
+                entry.last_used = self.clock;
+                entry.uses += 1;
+                self.hits += 1;
+                return Some(entry.bytes.clone());
+            }
+            Some(_) => self.remove(path),
+            None => {}
+        }
+        self.misses += 1;
+        None
+    }
+
+    /// Caches `bytes` as the content of `path`, evicting entries as needed.
+    pub fn insert(&mut self, path: PathBuf, etag: &str, bytes: Rc<[u8]>) {
+        if !self.admits(bytes.len() as u64) {
+            return;
+        }
+        self.remove(&path);
+        while self.used + bytes.len() > self.budget {
+            let policy = self.policy;
+            let victim = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| match policy {
+                    Policy::Lru => (0, e.last_used),
+                    Policy::Lfu => (e.uses, e.last_used),
+                })
+                .map(|(path, _)| path.clone());
+            match victim {
+                Some(victim) => {
+                    self.remove(&victim);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+        self.used += bytes.len();
+        let entry = Entry {
+            etag: etag.to_string(),
+            bytes,
+            last_used: self.clock,
+            uses: 1,
+        };
+        self.entries.insert(path, entry);
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.used -= entry.bytes.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(policy: Policy) -> ContentCache {
+        let mut cache = ContentCache::new(10, policy);
+        for name in ["a", "b"] {
+            assert!(cache.get(Path::new(name), "1").is_none());
+            cache.insert(PathBuf::from(name), "1", Rc::from(&b"1234"[..]));
+        }
+        cache
+    }
+
+    #[test]
+    fn test_eviction() {
+        let get = |cache: &mut ContentCache, name| cache.get(Path::new(name), "1").is_some();
+
+        // "a" is used more often, "b" more recently
+        let mut lru = cache(Policy::Lru);
+        let mut lfu = cache(Policy::Lfu);
+        for cache in [&mut lru, &mut lfu] {
+            assert!(get(cache, "a") && get(cache, "a") && get(cache, "b"));
+            cache.insert(PathBuf::from("c"), "1", Rc::from(&b"1234"[..]));
+            assert_eq!((cache.used, cache.evictions), (8, 1));
+        }
+        assert!(!get(&mut lru, "a") && get(&mut lru, "b"));
+        assert!(get(&mut lfu, "a") && !get(&mut lfu, "b"));
+        assert_eq!((lru.hits, lru.misses), (4, 3));
+    }
+
+    #[test]
+    fn test_stale_and_large() {
+        let mut cache = cache(Policy::Lru);
+        assert!(cache.get(Path::new("a"), "2").is_none());
+        assert_eq!(cache.used, 4);
+
+        assert!(!cache.admits(11));
+        cache.insert(PathBuf::from("c"), "1", Rc::from(&[0; 11][..]));
+        assert_eq!(cache.used, 4);
+        assert!(!ContentCache::new(0, Policy::Lru).admits(0));
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fmt,
+    fs::Metadata,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum FileError {
+    /// a target which is not a path or does not decode
+    BadPath(&'static str),
+    /// a path above the document root or through a symbolic link, or a
+    /// file which cannot be read
+    Forbidden,
+    NotFound,
+    Io(std::io::Error),
+}
+
+impl FileError {
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            FileError::BadPath(_) => (400, "Bad Request"),
+            FileError::Forbidden => (403, "Forbidden"),
+            FileError::NotFound => (404, "Not Found"),
+            FileError::Io(_) => (500, "Internal Server Error"),
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileError::BadPath(reason) => f.write_str(reason),
+            FileError::Forbidden => f.write_str("outside of the document root"),
+            FileError::NotFound => f.write_str("no such file"),
+            FileError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<std::io::Error> for FileError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::NotFound => FileError::NotFound,
+            ErrorKind::PermissionDenied => FileError::Forbidden,
+            _ => FileError::Io(e),
+        }
+    }
+}
+
+/// The file under `root` which a request target names, `index.html` for
+/// directories.
+pub fn resolve(root: &Path, target: &str) -> Result<PathBuf, FileError> {
+    let mut relative = relative(target)?;
+    if root.join(&relative).is_dir() {
+        relative.push("index.html");
+    }
+    confine(root, &relative)
+}
+
+/// `relative` joined to `root`. The path is confined to the root lexically
+/// only, so symbolic links below the root, which could lead out of it, are
+/// refused.
+pub fn confine(root: &Path, relative: &Path) -> Result<PathBuf, FileError> {
+    let mut path = root.to_owned();
+    for component in relative.components() {
+        path.push(component);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Err(FileError::Forbidden),
+            Ok(_) => {}
+            // nothing below a missing path can be a link
+            Err(_) => return Ok(root.join(relative)),
+        }
+    }
+    Ok(path)
+}
+
+/// The path which a request target names, relative to the document root.
+/// `.` and `..` are resolved without leaving the root.
+pub fn relative(target: &str) -> Result<PathBuf, FileError> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    if !path.starts_with('/') {
+        return Err(FileError::BadPath("request target is not a path"));
+    }
+    let path = urlencoding::decode(path).map_err(|_| FileError::BadPath("path is not UTF-8"))?;
+    if path.contains(['\0', '\\']) {
+        return Err(FileError::BadPath("invalid character in path"));
+    }
+
+    let mut relative = PathBuf::new();
+    for component in Path::new(path.as_ref()).components() {
+        match component {
+            Component::Normal(segment) => relative.push(segment),
+            Component::ParentDir => {
+                if !relative.pop() {
+                    return Err(FileError::Forbidden);
+                }
+            }
+            Component::RootDir | Component::CurDir => {}
+            Component::Prefix(_) => return Err(FileError::BadPath("invalid path")),
+        }
+    }
+    Ok(relative)
+}
+
+/// Bytes read and written at once when streaming a file.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// An open regular file under the document root, or a range of it.
+#[derive(Debug)]
+pub struct File {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    handle: std::fs::File,
+    /// bytes to send from the current position
+    len: u64,
+}
+
+impl File {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Restricts the content to `start..end`, which must be within the file.
+    pub fn range(mut self, start: u64, end: u64) -> std::io::Result<Self> {
+        self.handle.seek(SeekFrom::Start(start))?;
+        self.len = end - start;
+        Ok(self)
+    }
+
+    /// The whole content, in a buffer the size of the file.
+    pub fn read(self) -> Result<Vec<u8>, FileError> {
+        let mut buf = Vec::with_capacity(self.len as usize);
+        let len = self.len;
+        self.handle.take(len).read_to_end(&mut buf)?;
+        if (buf.len() as u64) < len {
+            return Err(FileError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(buf)
+    }
+
+    /// Reads the next bytes of the content into `buf`, 0 at its end. Fails
+    /// if the file is shorter than when it was opened, as the length has
+    /// been sent.
+    pub fn read_chunk(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let max = buf.len().min(self.len.try_into().unwrap_or(usize::MAX));
+        if max == 0 {
+            return Ok(0);
+        }
+        loop {
+            match self.handle.read(&mut buf[..max]) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    self.len -= n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes the content to `out` through a fixed-size buffer, so that
+    /// memory does not grow with the size of the file.
+    pub fn copy_to<W: Write>(mut self, out: &mut W) -> std::io::Result<()> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        loop {
+            match self.read_chunk(&mut chunk)? {
+                0 => return Ok(()),
+                n => out.write_all(&chunk[..n])?,
+            }
+        }
+    }
+}
+
+/// The file under `root` which `target` names.
+pub fn find(root: &Path, target: &str) -> Result<File, FileError> {
+    let path = resolve(root, target)?;
+    if !std::fs::metadata(&path)?.is_file() {
+        return Err(FileError::NotFound);
+    }
+    let handle = std::fs::File::open(&path)?;
+    // taken from the open handle, so that it describes the file which is
+    // read even if the path has been replaced since it was looked up
+    let metadata = handle.metadata()?;
+    Ok(File {
+        path,
+        len: metadata.len(),
+        metadata,
+        handle,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("static");
+        let path = |target| resolve(root, target).map_err(|e| e.status().0);
+
+        assert_eq!(
+            path("/archives/1972/img%20a.png?x=1"),
+            Ok(PathBuf::from("static/archives/1972/img a.png"))
+        );
+        assert_eq!(
+            path("/a/./b/../c.html"),
+            Ok(PathBuf::from("static/a/c.html"))
+        );
+        assert_eq!(path("//etc/passwd"), Ok(PathBuf::from("static/etc/passwd")));
+        assert_eq!(path("/../etc/passwd"), Err(403));
+        assert_eq!(path("/a/%2e%2e/%2E%2E/etc/passwd"), Err(403));
+        assert_eq!(path("/a%2f..%2f..%2fetc"), Err(403));
+        assert_eq!(path("/a%5c..%5cb"), Err(400));
+        assert_eq!(path("/a%00.html"), Err(400));
+        assert_eq!(path("/%ff"), Err(400));
+        assert_eq!(path("http://example.com/"), Err(400));
+        assert_eq!(path("*"), Err(400));
+    }
+
+    #[test]
+    fn test_find() {
+        let root = Path::new("src");
+        let file = find(root, "/main.rs").unwrap();
+        let len = file.metadata.len();
+        assert_eq!(file.read().unwrap().len() as u64, len);
+
+        let mut out = Vec::new();
+        find(root, "/main.rs").unwrap().copy_to(&mut out).unwrap();
+        assert_eq!(out, std::fs::read("src/main.rs").unwrap());
+
+        assert_eq!(find(root, "/missing.rs").unwrap_err().status().0, 404);
+        assert_eq!(find(root, "/").unwrap_err().status().0, 404);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = std::env::temp_dir().join(format!("wasmedge-app-links-{}", std::process::id()));
+        let root = dir.join("root");
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        std::fs::write(root.join("a/index.html"), "index").unwrap();
+        symlink(dir.join("secret.txt"), root.join("secret.txt")).unwrap();
+        symlink(&dir, root.join("up")).unwrap();
+
+        let status = |target| find(&root, target).map(|_| ()).map_err(|e| e.status().0);
+        assert_eq!(status("/a"), Ok(()));
+        assert_eq!(status("/secret.txt"), Err(403));
+        assert_eq!(status("/up/secret.txt"), Err(403));
+        assert_eq!(status("/up/missing.txt"), Err(403));
+        assert_eq!(status("/missing/secret.txt"), Err(404));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_chunks() {
+        let root = std::env::temp_dir().join(format!("wasmedge-app-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 5 / 2).map(|i| i as u8).collect();
+        std::fs::write(root.join("a.bin"), &content).unwrap();
+
+        let mut out = Vec::new();
+        find(&root, "/a.bin").unwrap().copy_to(&mut out).unwrap();
+        assert_eq!(out, content);
+
+        let range = CHUNK_SIZE as u64 - 10..CHUNK_SIZE as u64 * 2 + 10;
+        let file = || find(&root, "/a.bin").unwrap().range(range.start, range.end);
+        let mut out = Vec::new();
+        file().unwrap().copy_to(&mut out).unwrap();
+        let expected = &content[range.start as usize..range.end as usize];
+        assert_eq!(out, expected);
+        assert_eq!(file().unwrap().read().unwrap(), expected);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
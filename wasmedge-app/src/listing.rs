@@ -0,0 +1,153 @@
+use std::{fmt::Write, path::Path};
+
+use crate::files::{self, FileError};
+
+/// What to answer a request for a directory without `index.html` with.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Listing {
+    /// a redirect to this location, as the target lacks the trailing slash
+    /// relative links need
+    Redirect(String),
+    /// an HTML page listing the directory
+    Page(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    dir: bool,
+    len: u64,
+}
+
+/// The listing of the directory under `root` which `target` names.
+pub fn listing(root: &Path, target: &str) -> Result<Listing, FileError> {
+    let relative = files::relative(target)?;
+    let dir = files::confine(root, &relative)?;
+    if !dir.is_dir() {
+        return Err(FileError::NotFound);
+    }
+    let (path, query) = target.split_at(target.find(['?', '#']).unwrap_or(target.len()));
+    if !path.ends_with('/') {
+        let query = query.split('#').next().unwrap_or_default();
+        return Ok(Listing::Redirect(format!("{}/{}", path, query)));
+    }
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        // links are not served, so they are not listed either
+        let metadata = match entry.metadata() {
+            Ok(metadata) if !metadata.file_type().is_symlink() => metadata,
+            _ => continue,
+        };
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            dir: metadata.is_dir(),
+            len: metadata.len(),
+        });
+    }
+    // directories first
+    entries.sort_by(|a, b| b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name)));
+
+    let mut shown = String::from("/");
+    for component in relative.components() {
+        shown.push_str(&component.as_os_str().to_string_lossy());
+        shown.push('/');
+    }
+    Ok(Listing::Page(page(&shown, &entries)))
+}
+
+fn page(path: &str, entries: &[Entry]) -> String {
+    let title = format!("Index of {}", escape(path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n<table>\n",
+        title
+    );
+    if path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        let size = if entry.dir {
+            "-".to_string()
+        } else {
+            entry.len.to_string()
+        };
+        writeln!(
+            html,
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td align=\"right\">{}</td></tr>",
+            urlencoding::encode(&entry.name),
+            slash,
+            escape(&entry.name),
+            slash,
+            size
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// `s` as HTML text or attribute value.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing() {
+        let root =
+            std::env::temp_dir().join(format!("wasmedge-app-listing-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub dir/inner")).unwrap();
+        std::fs::write(root.join("b.txt"), "12345").unwrap();
+        std::fs::write(root.join("a<&>.html"), "").unwrap();
+        std::fs::write(root.join("sub dir/c.txt"), "1").unwrap();
+
+        let page = match listing(&root, "/").unwrap() {
+            Listing::Page(page) => page,
+            redirect => panic!("{:?}", redirect),
+        };
+        assert!(page.contains("<title>Index of /</title>"));
+        assert!(!page.contains("../"));
+        let rows: Vec<_> = page.lines().filter(|l| l.starts_with("<tr>")).collect();
+        assert_eq!(
+            rows,
+            [
+                "<tr><td><a href=\"sub%20dir/\">sub dir/</a></td><td align=\"right\">-</td></tr>",
+                "<tr><td><a href=\"a%3C%26%3E.html\">a&lt;&amp;&gt;.html</a></td><td align=\"right\">0</td></tr>",
+                "<tr><td><a href=\"b.txt\">b.txt</a></td><td align=\"right\">5</td></tr>",
+            ]
+        );
+
+        match listing(&root, "/sub%20dir/./?x=1").unwrap() {
+            Listing::Page(page) => {
+                assert!(page.contains("<h1>Index of /sub dir/</h1>"));
+                assert!(page.contains("<a href=\"../\">"));
+                assert!(page.contains("<a href=\"inner/\">"));
+            }
+            redirect => panic!("{:?}", redirect),
+        }
+        assert_eq!(
+            listing(&root, "/sub%20dir?x=1#y").unwrap(),
+            Listing::Redirect("/sub%20dir/?x=1".to_string())
+        );
+        assert_eq!(listing(&root, "/b.txt").unwrap_err().status().0, 404);
+        assert_eq!(listing(&root, "/missing/").unwrap_err().status().0, 404);
+        assert_eq!(listing(&root, "/../").unwrap_err().status().0, 403);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
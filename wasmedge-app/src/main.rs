@@ -1,88 +1,383 @@
-use bytecodec::{bytes::BytesEncoder, io::IoEncodeExt, DecodeExt, Encode};
-use httpcodec::{
-    BodyEncoder, HttpVersion, ReasonPhrase, Request, RequestDecoder, Response, ResponseEncoder,
-    StatusCode,
-};
+use access::Access;
+use cache::Validators;
+use content::{ContentCache, Policy};
+use files::{File, FileError};
+use httpcodec::Response;
+use listing::Listing;
+use range::{byte_range, ByteRange};
+use request::{Limits, Request, RequestError, RequestReader};
+use response::{header_field, response, status_response, write_response, Body, Mode};
+use stats::Stats;
 use std::{
-    fs::File,
-    io::{Read, Write},
+    io::Read,
     path::PathBuf,
+    rc::Rc,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+use wasmedge_wasi_socket::{
+    poll::{self, EventType, Subscription},
+    Shutdown, TcpListener, TcpStream,
 };
-use wasmedge_wasi_socket::{Shutdown, TcpListener, TcpStream};
 
-fn file_server(path: &str) -> Option<Vec<u8>> {
-    let mut target = PathBuf::from(urlencoding::decode(path).unwrap().into_owned());
+mod access;
+mod cache;
+mod concurrent;
+mod content;
+mod files;
+mod listing;
+mod mime;
+mod range;
+mod request;
+mod response;
+mod stats;
 
-    if target.is_dir() {
-        target.push("index.html");
-    }
+/// How connections are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServerMode {
+    /// one after the other, as a baseline
+    Sequential,
+    /// all at once, from a `poll` loop
+    Concurrent,
+}
 
-    if target.exists() && target.is_file() {
-        let mut f = File::open(target.as_path()).unwrap();
-        let mut buf = vec![];
-        f.read_to_end(&mut buf).unwrap();
-        Some(buf)
-    } else {
-        None
+impl FromStr for ServerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => Ok(ServerMode::Sequential),
+            "concurrent" => Ok(ServerMode::Concurrent),
+            _ => Err(format!("unknown server mode {}", s)),
+        }
     }
 }
 
-fn handle_http(req: Request<String>) -> bytecodec::Result<Response<Vec<u8>>> {
-    match file_server(req.request_target().as_str()) {
-        Some(v) => Ok(Response::new(
-            HttpVersion::V1_0,
-            StatusCode::new(200)?,
-            ReasonPhrase::new("")?,
-            v,
-        )),
-        None => Ok(Response::new(
-            HttpVersion::V1_0,
-            StatusCode::new(404)?,
-            ReasonPhrase::new("Not found")?,
-            "404 NOT FOUND".into(),
-        )),
+#[derive(Debug)]
+struct Config {
+    server_mode: ServerMode,
+    /// connections served at once at most in the concurrent mode, the
+    /// others waiting to be accepted
+    max_connections: usize,
+    limits: Limits,
+    root: PathBuf,
+    mode: Mode,
+    /// bytes of file contents kept in memory, none with 0
+    cache_size: usize,
+    cache_policy: Policy,
+    /// how long a connection may wait for the next request or its rest
+    idle_timeout: Duration,
+    /// requests served on a connection before it is closed
+    max_requests: usize,
+    /// whether a line is written to stdout for every request
+    access_log: bool,
+    /// whether directories without `index.html` are listed
+    listings: bool,
+}
+
+impl Config {
+    /// `SERVER_MODE` (`sequential` or `concurrent`), `MAX_CONNECTIONS`,
+    /// `DOCROOT`, `RESPONSE_MODE` (`buffered` or `streaming`), `CACHE_SIZE`
+    /// in bytes, `CACHE_POLICY` (`lru` or `lfu`), `MAX_HEADER_SIZE` and
+    /// `MAX_BODY_SIZE` in bytes, `IDLE_TIMEOUT` in seconds, `MAX_REQUESTS`
+    /// and `ACCESS_LOG` and `LISTINGS` (`true` or `false`), or the defaults.
+    fn from_env() -> Self {
+        let limits = Limits::default();
+        Self {
+            server_mode: var("SERVER_MODE", ServerMode::Concurrent),
+            max_connections: var("MAX_CONNECTIONS", 256).max(1),
+            limits: Limits {
+                max_head: var("MAX_HEADER_SIZE", limits.max_head),
+                max_body: var("MAX_BODY_SIZE", limits.max_body),
+            },
+            // `.` is the directory mapped with `wasmedge --dir .:<contents>`
+            root: var("DOCROOT", PathBuf::from(".")),
+            mode: var("RESPONSE_MODE", Mode::Streaming),
+            cache_size: var("CACHE_SIZE", 0),
+            cache_policy: var("CACHE_POLICY", Policy::Lru),
+            idle_timeout: Duration::from_secs(var("IDLE_TIMEOUT", 5)),
+            max_requests: var("MAX_REQUESTS", 100).max(1),
+            access_log: var("ACCESS_LOG", true),
+            listings: var("LISTINGS", false),
+        }
     }
 }
 
-fn handle_client(mut stream: TcpStream) -> std::io::Result<()> {
-    let mut buff = [0u8; 1024];
-    let mut data = Vec::new();
+/// What serving requests changes, shared by all connections.
+#[derive(Debug)]
+struct State {
+    cache: ContentCache,
+    stats: Stats,
+}
 
-    loop {
-        let n = stream.read(&mut buff)?;
-        data.extend_from_slice(&buff[0..n]);
-        if n < 1024 {
-            break;
-        }
+/// The environment variable `name` if set and valid, `default` otherwise.
+fn var<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn handle_http(
+    req: &Request,
+    config: &Config,
+    state: &mut State,
+) -> bytecodec::Result<Response<Body>> {
+    if !matches!(req.method.as_str(), "GET" | "HEAD") {
+        let detail = format!("{} is not supported", req.method);
+        let mut response = status_response(405, "Method Not Allowed", &detail);
+        response
+            .header_mut()
+            .add_field(header_field("Allow", "GET, HEAD")?);
+        return Ok(response);
     }
 
-    let mut decoder =
-        RequestDecoder::<httpcodec::BodyDecoder<bytecodec::bytes::Utf8Decoder>>::default();
+    if req.target.split(['?', '#']).next() == Some(stats::PATH) {
+        let json = state.stats.to_json(&state.cache);
+        let mut response = response(200, "OK", Body::Bytes(json.into_bytes()));
+        let mut header = response.header_mut();
+        header.add_field(header_field("Content-Type", "application/json")?);
+        header.add_field(header_field("Cache-Control", "no-store")?);
+        return Ok(response);
+    }
 
-    let req = match decoder.decode_from_bytes(data.as_slice()) {
-        Ok(req) => handle_http(req),
-        Err(e) => Err(e),
+    let file = match files::find(&config.root, &req.target) {
+        Ok(file) => file,
+        Err(FileError::NotFound) if config.listings => {
+            return listing_response(config, &req.target);
+        }
+        Err(e) => {
+            let (code, reason) = e.status();
+            return Ok(status_response(code, reason, &e));
+        }
     };
+    let validators = Validators::new(&file.metadata);
+    let mut response = if validators.not_modified(req) {
+        response(304, "Not Modified", Body::Bytes(Vec::new()))
+    } else {
+        let content_type = mime::content_type(&file.path);
+        let len = file.len();
+        // Range only applies to GET
+        let range = if req.method == "GET" && validators.range_applies(req) {
+            byte_range(req.header("range"), len)
+        } else {
+            ByteRange::Full
+        };
+        if range == ByteRange::Unsatisfiable {
+            let detail = format!("the file is {} bytes long", len);
+            let mut response = status_response(416, "Range Not Satisfiable", &detail);
+            let content_range = format!("bytes */{}", len);
+            response
+                .header_mut()
+                .add_field(header_field("Content-Range", &content_range)?);
+            return Ok(response);
+        }
 
-    let r = match req {
-        Ok(r) => r,
+        let mut response =
+            match file_response(req, config, &mut state.cache, file, &validators, &range) {
+                Ok(response) => response,
+                Err(e) => {
+                    let (code, reason) = e.status();
+                    return Ok(status_response(code, reason, &e));
+                }
+            };
+        let mut header = response.header_mut();
+        header.add_field(header_field("Content-Type", content_type)?);
+        header.add_field(header_field("Accept-Ranges", "bytes")?);
+        if let ByteRange::Partial(range) = range {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+            header.add_field(header_field("Content-Range", &content_range)?);
+        }
+        response
+    };
+
+    let mut header = response.header_mut();
+    header.add_field(header_field("ETag", &validators.etag)?);
+    if let Some(last_modified) = validators.last_modified_header() {
+        header.add_field(header_field("Last-Modified", &last_modified)?);
+    }
+    Ok(response)
+}
+
+/// A generated listing of the directory `target` names, or a 404 when it is
+/// no directory.
+fn listing_response(config: &Config, target: &str) -> bytecodec::Result<Response<Body>> {
+    let mut response = match listing::listing(&config.root, target) {
+        Ok(Listing::Page(html)) => {
+            let mut response = response(200, "OK", Body::Bytes(html.into_bytes()));
+            response
+                .header_mut()
+                .add_field(header_field("Content-Type", "text/html; charset=utf-8")?);
+            response
+        }
+        Ok(Listing::Redirect(location)) => {
+            let mut response = status_response(301, "Moved Permanently", &location);
+            response
+                .header_mut()
+                .add_field(header_field("Location", &location)?);
+            response
+        }
         Err(e) => {
-            let err = format!("{:?}", e);
-            Response::new(
-                HttpVersion::V1_0,
-                StatusCode::new(500).unwrap(),
-                ReasonPhrase::new(&err.to_string()).unwrap(),
-                err.into_bytes(),
-            )
+            let (code, reason) = e.status();
+            status_response(code, reason, &e)
         }
     };
+    response
+        .header_mut()
+        .add_field(header_field("Cache-Control", "no-cache")?);
+    Ok(response)
+}
+
+/// A 200 with `file`, or a 206 with the part of it `range` asks for. The
+/// content comes from `cache` when it is there or fits, with `X-Cache` saying
+/// which.
+fn file_response(
+    req: &Request,
+    config: &Config,
+    cache: &mut ContentCache,
+    file: File,
+    validators: &Validators,
+    range: &ByteRange,
+) -> Result<Response<Body>, FileError> {
+    let (code, reason) = match range {
+        ByteRange::Partial(_) => (206, "Partial Content"),
+        _ => (200, "OK"),
+    };
+    // HEAD requests need the length only
+    if req.method != "HEAD" && cache.admits(file.len()) {
+        let (bytes, status) = match cache.get(&file.path, &validators.etag) {
+            Some(bytes) => (bytes, "HIT"),
+            None => {
+                let path = file.path.clone();
+                let bytes = Rc::<[u8]>::from(file.read()?);
+                cache.insert(path, &validators.etag, bytes.clone());
+                (bytes, "MISS")
+            }
+        };
+        let range = match range {
+            ByteRange::Partial(range) => range.start as usize..range.end as usize,
+            _ => 0..bytes.len(),
+        };
+        let mut response = response(code, reason, Body::Shared(bytes, range));
+        response
+            .header_mut()
+            .add_field(header_field("X-Cache", status).unwrap());
+        return Ok(response);
+    }
+
+    let file = match range {
+        ByteRange::Partial(range) => file.range(range.start, range.end)?,
+        _ => file,
+    };
+    let body = if req.method == "HEAD" {
+        Body::File(file)
+    } else {
+        Body::file(file, config.mode)?
+    };
+    Ok(response(code, reason, body))
+}
+
+/// Reads which fail with `TimedOut` when the client sends nothing for the
+/// idle timeout.
+struct Idle<'a> {
+    stream: &'a TcpStream,
+    timeout: Duration,
+}
+
+impl Read for Idle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let deadline = SystemTime::now() + self.timeout;
+        let subscription = Subscription::io(0, self.stream, true, false, Some(deadline));
+        for event in poll::poll(&[subscription])? {
+            match event.event_type {
+                EventType::Read => return self.stream.read(buf),
+                EventType::Error(e) => return Err(e),
+                EventType::Timeout | EventType::Write => {}
+            }
+        }
+        Err(std::io::ErrorKind::TimedOut.into())
+    }
+}
 
-    let mut encoder = ResponseEncoder::new(BodyEncoder::new(BytesEncoder::new()));
-    encoder.start_encoding(r).unwrap();
-    let mut data = Vec::new();
-    encoder.encode_all(&mut data).unwrap();
+/// The answer to the `served`th request of a connection, and whether the
+/// connection stays open after it.
+fn respond(
+    req: &Request,
+    config: &Config,
+    state: &mut State,
+    served: usize,
+) -> (Response<Body>, bool) {
+    let keep_alive = req.keep_alive() && served < config.max_requests;
+    let mut r = match handle_http(req, config, state) {
+        Ok(r) => r,
+        Err(e) => status_response(500, "Internal Server Error", &e),
+    };
+    state.stats.record(r.status_code().as_u16());
+    let mut header = r.header_mut();
+    if keep_alive {
+        // persistent by default from HTTP/1.1 on
+        if req.version == "HTTP/1.0" {
+            header.add_field(header_field("Connection", "keep-alive").unwrap());
+        }
+        let options = format!(
+            "timeout={}, max={}",
+            config.idle_timeout.as_secs(),
+            config.max_requests - served
+        );
+        header.add_field(header_field("Keep-Alive", &options).unwrap());
+    } else {
+        header.add_field(header_field("Connection", "close").unwrap());
+    }
+    (r, keep_alive)
+}
+
+/// The answer to a request which could not be read, after which the
+/// connection is closed as the rest of the request is left unread. `None`
+/// when there is no one to answer.
+fn error_response(e: &RequestError, stats: &mut Stats) -> Option<Response<Body>> {
+    let (code, reason) = e.status()?;
+    stats.record(code);
+    let mut r = status_response(code, reason, e);
+    r.header_mut()
+        .add_field(header_field("Connection", "close").unwrap());
+    Some(r)
+}
 
-    stream.write_all(&data)?;
+/// Serves the requests of a connection until the client closes it, stays
+/// idle for too long, asks for it to be closed or reaches the limit of
+/// requests.
+fn handle_client(stream: TcpStream, config: &Config, state: &mut State) -> std::io::Result<()> {
+    let mut reader = RequestReader::new(config.limits);
+    let mut served = 0;
+    loop {
+        let mut idle = Idle {
+            stream: &stream,
+            timeout: config.idle_timeout,
+        };
+        let (r, head, keep_alive, mut access) = match reader.read(&mut idle) {
+            Ok(Some(req)) => {
+                served += 1;
+                let access = Access::new(Some(&req));
+                let (r, keep_alive) = respond(&req, config, state, served);
+                (r, req.method == "HEAD", keep_alive, access)
+            }
+            // nothing to answer
+            Ok(None) => break,
+            Err(e) => match error_response(&e, &mut state.stats) {
+                Some(r) => (r, false, false, Access::new(None)),
+                None => break,
+            },
+        };
+        access.status = r.status_code().as_u16();
+        access.sent = write_response(&mut &stream, r, head)?;
+        state.stats.bytes_sent += access.sent;
+        if config.access_log {
+            access.log();
+        }
+        if !keep_alive {
+            break;
+        }
+    }
     stream.shutdown(Shutdown::Both)?;
     Ok(())
 }
@@ -90,8 +385,17 @@ fn handle_client(mut stream: TcpStream) -> std::io::Result<()> {
 fn main() -> std::io::Result<()> {
     let port = std::env::var("PORT").unwrap_or_else(|_| "1234".to_string());
     println!("new connection at {}", port);
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port), false)?;
-    loop {
-        let _ = handle_client(listener.accept(false)?.0);
+    let config = Config::from_env();
+    let nonblocking = config.server_mode == ServerMode::Concurrent;
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port), nonblocking)?;
+    let mut state = State {
+        cache: ContentCache::new(config.cache_size, config.cache_policy),
+        stats: Stats::new(),
+    };
+    match config.server_mode {
+        ServerMode::Concurrent => concurrent::serve(&listener, &config, &mut state),
+        ServerMode::Sequential => loop {
+            let _ = handle_client(listener.accept(false)?.0, &config, &mut state);
+        },
     }
 }
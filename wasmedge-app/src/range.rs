@@ -0,0 +1,82 @@
+use std::ops::Range;
+
+/// What to answer a `Range` header with, for a file of a given length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// the whole file, for requests without a range or with one we do not
+    /// serve, like several ranges at once
+    Full,
+    /// a 206 with these bytes
+    Partial(Range<u64>),
+    /// a 416, as the range starts past the end
+    Unsatisfiable,
+}
+
+/// The range of `len` bytes which a `Range: bytes=...` header asks for.
+/// Invalid headers are ignored, as RFC 7233 allows.
+pub fn byte_range(header: Option<&str>, len: u64) -> ByteRange {
+    let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+    let number = |s: &str| {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            None
+        } else {
+            s.parse::<u64>().ok()
+        }
+    };
+
+    match (number(first), number(last)) {
+        // the last `n` bytes
+        (None, Some(n)) if first.is_empty() => {
+            if n == 0 || len == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(len.saturating_sub(n)..len)
+            }
+        }
+        (Some(start), None) if last.is_empty() => partial(start, len, len),
+        (Some(start), Some(end)) if start <= end => partial(start, end.saturating_add(1), len),
+        _ => ByteRange::Full,
+    }
+}
+
+fn partial(start: u64, end: u64, len: u64) -> ByteRange {
+    if start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(start..end.min(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_range() {
+        let range = |header| byte_range(Some(header), 1000);
+
+        assert_eq!(range("bytes=0-499"), ByteRange::Partial(0..500));
+        assert_eq!(range("bytes=500-"), ByteRange::Partial(500..1000));
+        assert_eq!(range("bytes=-200"), ByteRange::Partial(800..1000));
+        assert_eq!(range("bytes=-2000"), ByteRange::Partial(0..1000));
+        assert_eq!(range("bytes=900-1999"), ByteRange::Partial(900..1000));
+        assert_eq!(range("bytes=1000-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=-5"), 0), ByteRange::Unsatisfiable);
+
+        assert_eq!(byte_range(None, 1000), ByteRange::Full);
+        assert_eq!(range("bytes=0-1,5-9"), ByteRange::Full);
+        assert_eq!(range("bytes=5-1"), ByteRange::Full);
+        assert_eq!(range("bytes=a-"), ByteRange::Full);
+        assert_eq!(range("bytes=+1-2"), ByteRange::Full);
+        assert_eq!(range("items=0-1"), ByteRange::Full);
+        assert_eq!(range("bytes=-"), ByteRange::Full);
+    }
+}
@@ -0,0 +1,370 @@
+use std::{fmt, io::Read};
+
+/// Bounds on what a client may send, so that a request cannot exhaust the
+/// memory of the instance.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// request line and headers, terminator included
+    pub max_head: usize,
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_head: 8 * 1024,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    /// e.g. `HTTP/1.1`
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of the first `name` header, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether the client asks for the connection to stay open, which is
+    /// the default from HTTP/1.1 on.
+    pub fn keep_alive(&self) -> bool {
+        let has = |option: &str| {
+            self.headers
+                .iter()
+                .filter(|(n, _)| n.eq_ignore_ascii_case("connection"))
+                .flat_map(|(_, v)| v.split(','))
+                .any(|v| v.trim().eq_ignore_ascii_case(option))
+        };
+        if self.version == "HTTP/1.0" {
+            has("keep-alive")
+        } else {
+            !has("close")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    /// a request which cannot be parsed, or with contradicting headers
+    BadRequest(&'static str),
+    /// request line and headers over `Limits::max_head`
+    HeadTooLarge,
+    /// a `Content-Length` over `Limits::max_body`
+    BodyTooLarge,
+    /// a `Transfer-Encoding`, which bodies are never sent with in practice
+    NotImplemented,
+    /// the client closed the connection in the middle of a request
+    Incomplete,
+    Io(std::io::Error),
+}
+
+impl RequestError {
+    /// The status to answer with, `None` when no answer can be sent.
+    pub fn status(&self) -> Option<(u16, &'static str)> {
+        match self {
+            RequestError::BadRequest(_) | RequestError::HeadTooLarge => Some((400, "Bad Request")),
+            RequestError::BodyTooLarge => Some((413, "Payload Too Large")),
+            RequestError::NotImplemented => Some((501, "Not Implemented")),
+            RequestError::Incomplete | RequestError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::BadRequest(reason) => f.write_str(reason),
+            RequestError::HeadTooLarge => f.write_str("request headers too large"),
+            RequestError::BodyTooLarge => f.write_str("request body too large"),
+            RequestError::NotImplemented => f.write_str("transfer encodings are not supported"),
+            RequestError::Incomplete => f.write_str("connection closed mid-request"),
+            RequestError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<std::io::Error> for RequestError {
+    fn from(e: std::io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+/// Reads requests off a stream whatever the way they are split into reads.
+/// Bytes read past the end of a request are kept for the next one.
+#[derive(Debug, Default)]
+pub struct RequestReader {
+    limits: Limits,
+    buf: Vec<u8>,
+}
+
+impl RequestReader {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The next request, `None` if the client closed the connection
+    /// before sending anything.
+    pub fn read<R: Read>(&mut self, stream: &mut R) -> Result<Option<Request>, RequestError> {
+        loop {
+            if let Some(request) = self.parse()? {
+                return Ok(Some(request));
+            }
+            if self.fill(stream)? == 0 {
+                return if self.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(RequestError::Incomplete)
+                };
+            }
+        }
+    }
+
+    /// The next request out of what has been read so far, `None` until it
+    /// is complete. Errors as soon as a limit is exceeded.
+    pub fn parse(&mut self) -> Result<Option<Request>, RequestError> {
+        let head_len = match find(&self.buf, b"\r\n\r\n") {
+            Some(end) => end + 4,
+            None if self.buf.len() >= self.limits.max_head => {
+                return Err(RequestError::HeadTooLarge)
+            }
+            None => return Ok(None),
+        };
+        if head_len > self.limits.max_head {
+            return Err(RequestError::HeadTooLarge);
+        }
+
+        let mut request = parse_head(&self.buf[..head_len - 4])?;
+        if request.header("transfer-encoding").is_some() {
+            return Err(RequestError::NotImplemented);
+        }
+        let body_len = content_length(&request)?;
+        if body_len > self.limits.max_body {
+            return Err(RequestError::BodyTooLarge);
+        }
+        if self.buf.len() < head_len + body_len {
+            return Ok(None);
+        }
+
+        request.body = self.buf[head_len..head_len + body_len].to_vec();
+        self.buf.drain(..head_len + body_len);
+        Ok(Some(request))
+    }
+
+    /// Reads once from `stream`, 0 bytes at the end of the stream.
+    pub fn fill<R: Read>(&mut self, stream: &mut R) -> std::io::Result<usize> {
+        let mut chunk = [0u8; 4096];
+        let n = loop {
+            match stream.read(&mut chunk) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+
+    /// Whether nothing of a next request has been read.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_head(head: &[u8]) -> Result<Request, RequestError> {
+    let head =
+        std::str::from_utf8(head).map_err(|_| RequestError::BadRequest("non UTF-8 headers"))?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, target, version) = match (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) {
+        (Some(m), Some(t), Some(v), None) if !m.is_empty() && !t.is_empty() => (m, t, v),
+        _ => return Err(RequestError::BadRequest("malformed request line")),
+    };
+    if !matches!(version, "HTTP/1.0" | "HTTP/1.1") {
+        return Err(RequestError::BadRequest("unsupported HTTP version"));
+    }
+    if !method.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(RequestError::BadRequest("malformed method"));
+    }
+
+    let mut headers = Vec::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(RequestError::BadRequest("malformed header"))?;
+        if name.is_empty() || name.trim_end() != name {
+            return Err(RequestError::BadRequest("malformed header"));
+        }
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        version: version.to_string(),
+        headers,
+        body: Vec::new(),
+    })
+}
+
+fn content_length(request: &Request) -> Result<usize, RequestError> {
+    let mut length = None;
+    for (_, value) in request
+        .headers
+        .iter()
+        .filter(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+    {
+        let value = value
+            .parse::<usize>()
+            .map_err(|_| RequestError::BadRequest("malformed Content-Length"))?;
+        if length.is_some_and(|length| length != value) {
+            return Err(RequestError::BadRequest("conflicting Content-Length"));
+        }
+        length = Some(value);
+    }
+    Ok(length.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io};
+
+    use super::*;
+
+    /// Gives `chunks` one read at a time, like TCP segments.
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]]) -> Self {
+            Self(chunks.iter().map(|c| c.to_vec()).collect())
+        }
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let chunk = match self.0.front_mut() {
+                Some(chunk) => chunk,
+                None => return Ok(0),
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.0.pop_front();
+            }
+            Ok(n)
+        }
+    }
+
+    fn read(chunks: &[&[u8]]) -> Result<Option<Request>, RequestError> {
+        RequestReader::default().read(&mut Chunks::new(chunks))
+    }
+
+    #[test]
+    fn test_read_split() {
+        let request = read(&[b"GET /archives/1972/ HT", b"TP/1.1\r\nHost: a\r", b"\n\r\n"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "/archives/1972/");
+        assert_eq!(request.header("host"), Some("a"));
+        assert!(request.body.is_empty());
+
+        let request = read(&[b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhe", b"llo"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn test_read_exact_multiple() {
+        // the old reader waited for a short read which never came
+        let padding = "x".repeat(1024 - "GET / HTTP/1.1\r\nX: \r\n\r\n".len());
+        let head = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", padding);
+        assert_eq!(head.len(), 1024);
+        let request = read(&[head.as_bytes()]).unwrap().unwrap();
+        assert_eq!(request.header("x"), Some(padding.as_str()));
+    }
+
+    #[test]
+    fn test_read_pipelined() {
+        let mut reader = RequestReader::default();
+        let mut stream = Chunks::new(&[b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n"]);
+        assert_eq!(reader.read(&mut stream).unwrap().unwrap().target, "/a");
+        assert_eq!(reader.read(&mut stream).unwrap().unwrap().target, "/b");
+        assert!(reader.read(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let keep_alive = |head: &[u8]| read(&[head]).unwrap().unwrap().keep_alive();
+        assert!(keep_alive(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            b"GET / HTTP/1.0\r\nConnection: TE, keep-alive\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let status = |chunks: &[&[u8]]| read(chunks).unwrap_err().status();
+
+        assert_eq!(status(&[b"GET /\r\n\r\n"]), Some((400, "Bad Request")));
+        assert_eq!(
+            status(&[b"GET / HTTP/2\r\n\r\n"]),
+            Some((400, "Bad Request"))
+        );
+        assert_eq!(
+            status(&[b"GET / HTTP/1.1\r\nno colon\r\n\r\n"]),
+            Some((400, "Bad Request"))
+        );
+        assert_eq!(
+            status(&[b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"]),
+            Some((400, "Bad Request"))
+        );
+        assert_eq!(
+            status(&[format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(8192)).as_bytes()]),
+            Some((400, "Bad Request"))
+        );
+        assert_eq!(
+            status(&[b"POST / HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n"]),
+            Some((413, "Payload Too Large"))
+        );
+        assert_eq!(
+            status(&[b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"]),
+            Some((501, "Not Implemented"))
+        );
+        assert_eq!(status(&[b"GET / HTTP/1.1\r\n"]), None);
+        assert_eq!(
+            status(&[b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhe"]),
+            None
+        );
+        assert!(read(&[]).unwrap().is_none());
+    }
+}
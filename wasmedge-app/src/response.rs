@@ -0,0 +1,224 @@
+use std::{
+    fmt,
+    io::{BufWriter, Write},
+    ops::Range,
+    rc::Rc,
+};
+
+use bytecodec::{io::IoEncodeExt, Encode};
+use httpcodec::{
+    HeaderField, HttpVersion, NoBodyEncoder, ReasonPhrase, Response, ResponseEncoder, StatusCode,
+};
+
+use crate::files::{File, CHUNK_SIZE};
+
+/// How files are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// read whole into memory, then written
+    Buffered,
+    /// copied to the socket in fixed-size chunks
+    Streaming,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffered" => Ok(Mode::Buffered),
+            "streaming" => Ok(Mode::Streaming),
+            _ => Err(format!("unknown response mode {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    File(File),
+    /// a range of content shared with the cache
+    Shared(Rc<[u8]>, Range<usize>),
+}
+
+impl Body {
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(file) => file.len(),
+            Body::Shared(_, range) => range.len() as u64,
+        }
+    }
+
+    /// Takes the next bytes of the body into `buf`, 0 at its end.
+    pub fn read_chunk(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Body::File(file) => file.read_chunk(buf),
+            Body::Shared(bytes, range) => {
+                let n = buf.len().min(range.len());
+                buf[..n].copy_from_slice(&bytes[range.start..range.start + n]);
+                range.start += n;
+                Ok(n)
+            }
+            Body::Bytes(bytes) => {
+                let n = buf.len().min(bytes.len());
+                buf[..n].copy_from_slice(&bytes[..n]);
+                bytes.drain(..n);
+                Ok(n)
+            }
+        }
+    }
+
+    /// The body to send `file` with in `mode`.
+    pub fn file(file: File, mode: Mode) -> Result<Self, crate::files::FileError> {
+        match mode {
+            Mode::Buffered => Ok(Body::Bytes(file.read()?)),
+            Mode::Streaming => Ok(Body::File(file)),
+        }
+    }
+}
+
+/// `HeaderField::new` rejects spaces, which RFC 7230 allows inside values
+/// and dates and media type parameters need.
+pub fn header_field<'n, 'v>(
+    name: &'n str,
+    value: &'v str,
+) -> bytecodec::Result<HeaderField<'n, 'v>> {
+    let valid = |b: u8| b == b' ' || b == b'\t' || (0x21..=0x7e).contains(&b);
+    if !value.bytes().all(valid) || value.trim_matches([' ', '\t']) != value {
+        return Err(bytecodec::ErrorKind::InvalidInput.into());
+    }
+    HeaderField::new(name, "")?;
+    // SAFETY: both the name and the value have been checked
+    Ok(unsafe { HeaderField::new_unchecked(name, value) })
+}
+
+/// A response with `code`. HTTP/1.0 clients are answered as HTTP/1.1 too, as
+/// RFC 7230 has servers send the highest version they support.
+pub fn response(code: u16, reason: &str, body: Body) -> Response<Body> {
+    Response::new(
+        HttpVersion::V1_1,
+        StatusCode::new(code).unwrap(),
+        ReasonPhrase::new(reason).unwrap(),
+        body,
+    )
+}
+
+/// A response with `code` and a short plain text body.
+pub fn status_response(code: u16, reason: &str, detail: &dyn fmt::Display) -> Response<Body> {
+    let body = format!("{} {}: {}", code, reason.to_uppercase(), detail);
+    let mut response = response(code, reason, Body::Bytes(body.into_bytes()));
+    response
+        .header_mut()
+        .add_field(header_field("Content-Type", "text/plain; charset=utf-8").unwrap());
+    response
+}
+
+/// The head of `response` with its `Content-Length`, and the body to send
+/// after it: none for HEAD requests and 304s, though HEAD responses keep the
+/// `Content-Length` of the body.
+pub fn encode_head(
+    response: Response<Body>,
+    head: bool,
+) -> std::io::Result<(Vec<u8>, Option<Body>)> {
+    let not_modified = response.status_code().as_u16() == 304;
+    let (mut response, body) = response.take_body();
+    if !not_modified {
+        let len = body.len().to_string();
+        response
+            .header_mut()
+            .add_field(header_field("Content-Length", &len).unwrap());
+    }
+
+    let mut encoder = ResponseEncoder::new(NoBodyEncoder);
+    let mut data = Vec::new();
+    encoder
+        .start_encoding(response)
+        .and_then(|()| encoder.encode_all(&mut data))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let body = if head || not_modified {
+        None
+    } else {
+        Some(body)
+    };
+    Ok((data, body))
+}
+
+/// Writes `response` to a blocking stream, returning the bytes written.
+///
+/// The head goes out with the start of the body: written apart, it leaves a
+/// small segment which the client only acknowledges after its delayed ACK
+/// timeout on kept-alive connections.
+pub fn write_response<W: Write>(
+    out: W,
+    response: Response<Body>,
+    head: bool,
+) -> std::io::Result<u64> {
+    let (data, body) = encode_head(response, head)?;
+    let len = data.len() as u64 + body.as_ref().map_or(0, Body::len);
+    let mut out = BufWriter::with_capacity(2 * CHUNK_SIZE, out);
+    out.write_all(&data)?;
+    match body {
+        Some(Body::Bytes(bytes)) => out.write_all(&bytes)?,
+        Some(Body::File(file)) => file.copy_to(&mut out)?,
+        Some(Body::Shared(bytes, range)) => out.write_all(&bytes[range])?,
+        None => {}
+    }
+    out.flush()?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::files;
+
+    fn written(response: Response<Body>, head: bool) -> String {
+        let mut out = Vec::new();
+        let len = write_response(&mut out, response, head).unwrap();
+        assert_eq!(len, out.len() as u64);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_response() {
+        let ok = || response(200, "OK", Body::Bytes(b"hello".to_vec()));
+        assert_eq!(
+            written(ok(), false),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+        );
+        assert_eq!(
+            written(ok(), true),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"
+        );
+        assert_eq!(
+            written(
+                response(304, "Not Modified", Body::Bytes(Vec::new())),
+                false
+            ),
+            "HTTP/1.1 304 Not Modified\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_modes() {
+        let root = Path::new("src");
+        let send = |mode| {
+            let file = files::find(root, "/main.rs").unwrap();
+            written(response(200, "OK", Body::file(file, mode).unwrap()), false)
+        };
+        let streamed = send(Mode::Streaming);
+        assert_eq!(streamed, send(Mode::Buffered));
+        assert!(streamed.ends_with(&std::fs::read_to_string("src/main.rs").unwrap()));
+    }
+
+    #[test]
+    fn test_header_field() {
+        assert!(header_field("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT").is_ok());
+        assert!(header_field("X", "a\r\nInjected: 1").is_err());
+        assert!(header_field("X", " padded").is_err());
+        assert!(header_field("Bad Name", "a").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::{collections::BTreeMap, fmt::Write, time::Instant};
+
+use crate::content::ContentCache;
+
+/// The path the statistics are served at, before any file.
+pub const PATH: &str = "/_stats";
+
+/// What the server has done since it started, as it sees it.
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    /// requests answered, and requests which could not be read but were
+    pub requests: u64,
+    /// bytes of responses written to sockets
+    pub bytes_sent: u64,
+    /// responses by status code
+    pub statuses: BTreeMap<u16, u64>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            requests: 0,
+            bytes_sent: 0,
+            statuses: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, status: u16) {
+        self.requests += 1;
+        *self.statuses.entry(status).or_default() += 1;
+    }
+
+    /// The statistics with those of `cache` as a JSON object. The errors are
+    /// the 4xx and 5xx responses.
+    pub fn to_json(&self, cache: &ContentCache) -> String {
+        let mut errors = String::new();
+        for (status, count) in self.statuses.range(400..) {
+            let sep = if errors.is_empty() { "" } else { "," };
+            write!(errors, "{}\"{}\":{}", sep, status, count).unwrap();
+        }
+        let memory = match memory_size() {
+            Some(bytes) => bytes.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            concat!(
+                "{{\"requests\":{},\"bytes_sent\":{},\"errors\":{{{}}},",
+                "\"uptime_secs\":{:.3},\"memory_bytes\":{},",
+                "\"cache\":{{\"hits\":{},\"misses\":{},\"evictions\":{},\"bytes\":{}}}}}"
+            ),
+            self.requests,
+            self.bytes_sent,
+            errors,
+            self.started.elapsed().as_secs_f64(),
+            memory,
+            cache.hits,
+            cache.misses,
+            cache.evictions,
+            cache.used,
+        )
+    }
+}
+
+/// The size of the wasm linear memory, which only grows. `None` when not
+/// built for wasm.
+fn memory_size() -> Option<usize> {
+    #[cfg(target_arch = "wasm32")]
+    return Some(core::arch::wasm32::memory_size(0) * 65536);
+    #[cfg(not(target_arch = "wasm32"))]
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::Policy;
+
+    #[test]
+    fn test_to_json() {
+        let mut stats = Stats::new();
+        for status in [200, 404, 200, 500, 304, 404] {
+            stats.record(status);
+        }
+        stats.bytes_sent = 1234;
+        let json = stats.to_json(&ContentCache::new(0, Policy::Lru));
+
+        assert!(json.starts_with(
+            "{\"requests\":6,\"bytes_sent\":1234,\"errors\":{\"404\":2,\"500\":1},\"uptime_secs\":"
+        ));
+        assert!(json.ends_with(",\"cache\":{\"hits\":0,\"misses\":0,\"evictions\":0,\"bytes\":0}}"));
+    }
+}